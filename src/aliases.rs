@@ -0,0 +1,283 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use darklua_core::nodes;
+use darklua_core::process::{DefaultVisitor, NodeProcessor, NodeVisitor};
+use darklua_core::rules::PathRequireMode;
+use darklua_core::Resources;
+use serde::Deserialize;
+
+use crate::commands::version::dependency_graph::is_data_module;
+use crate::commands::version::dependency_visitor::{is_require_call, require_literal};
+use crate::config::Settings;
+use crate::paths::relative_to;
+
+pub const LUAURC_FILE_NAME: &str = ".luaurc";
+
+/// Require aliases by name, without the leading `@`: `require("@shared/http")` uses `shared`
+pub type Aliases = BTreeMap<String, PathBuf>;
+
+#[derive(Debug, Default, Deserialize)]
+struct Luaurc {
+    #[serde(default)]
+    aliases: BTreeMap<String, String>,
+}
+
+/// `.luaurc` files are JSON with comments and trailing commas allowed, like luau-lsp reads them
+fn strip_jsonc(content: &str) -> String {
+    let mut stripped = String::with_capacity(content.len());
+    let mut chars = content.chars().peekable();
+    let mut in_string = false;
+
+    while let Some(char) = chars.next() {
+        if in_string {
+            stripped.push(char);
+            match char {
+                '\\' => stripped.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match (char, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                stripped.push(char);
+            }
+            ('/', Some('/')) => while chars.next_if(|next| *next != '\n').is_some() {},
+            ('/', Some('*')) => {
+                chars.next();
+                let mut previous = ' ';
+                for next in chars.by_ref() {
+                    if previous == '*' && next == '/' {
+                        break;
+                    }
+                    previous = next;
+                }
+            }
+            (',', _) => {
+                let rest = chars.clone().find(|next| !next.is_whitespace());
+                if !matches!(rest, Some('}') | Some(']')) {
+                    stripped.push(char);
+                }
+            }
+            _ => stripped.push(char),
+        }
+    }
+
+    stripped
+}
+
+fn read_luaurc(path: &Path) -> Result<Aliases> {
+    let luaurc: Luaurc = serde_json::from_str(&strip_jsonc(&std::fs::read_to_string(path)?))
+        .map_err(|e| anyhow::anyhow!("Invalid {}: {}", path.display(), e))?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    Ok(luaurc
+        .aliases
+        .into_iter()
+        .map(|(name, target)| (name, directory.join(target)))
+        .collect())
+}
+
+/// Aliases declared in `[settings.aliases]`, relative to the working directory like flow paths
+pub fn configured_aliases(settings: &Settings) -> Aliases {
+    settings
+        .aliases
+        .iter()
+        .flatten()
+        .map(|(name, target)| {
+            (
+                name.trim_start_matches('@').to_string(),
+                PathBuf::from(target),
+            )
+        })
+        .collect()
+}
+
+/// Aliases visible from `directory`, resolved like luau-lsp does: every `.luaurc` from the
+/// root down to `directory`, the closest one winning, then the aliases of opacity.toml on top
+pub fn aliases_for(directory: &Path, configured: &Aliases) -> Result<Aliases> {
+    let current_dir = std::env::current_dir()?;
+    let absolute_directory = current_dir.join(directory);
+
+    let mut aliases = Aliases::new();
+    let mut ancestors = absolute_directory.ancestors().collect::<Vec<_>>();
+    ancestors.reverse();
+    for ancestor in ancestors {
+        let luaurc = ancestor.join(LUAURC_FILE_NAME);
+        if luaurc.is_file() {
+            for (name, target) in read_luaurc(&luaurc)? {
+                aliases.insert(name, relative_to(&target, &current_dir));
+            }
+        }
+    }
+
+    aliases.extend(configured.clone());
+    Ok(aliases)
+}
+
+/// Require aliases a flow is bundled with
+#[derive(Debug, Default)]
+pub struct FlowAliases {
+    /// The aliases of the entry file, plus the scoped ones of the modules that see others
+    pub aliases: Aliases,
+    /// Every module of the flow with its source, requires rescoped, only when some module sees
+    /// other aliases than the entry file
+    pub sources: Vec<(PathBuf, String)>,
+}
+
+/// `@<name>~<scope>`, the alias `name` as seen from the modules of `scope`
+fn scoped_alias(name: &str, scope: usize) -> String {
+    format!("{}~{}", name, scope)
+}
+
+/// Line and literal of every `require` call of a block parsed with its tokens
+#[derive(Default)]
+struct RequireCollector {
+    requires: Vec<(usize, String)>,
+}
+
+impl NodeProcessor for RequireCollector {
+    fn process_function_call(&mut self, call: &mut nodes::FunctionCall) {
+        let nodes::Prefix::Identifier(identifier) = call.get_prefix() else {
+            return;
+        };
+        let line = identifier
+            .get_token()
+            .and_then(|token| token.get_line_number());
+        if let (true, Some(line), Some(literal)) =
+            (is_require_call(call), line, require_literal(call))
+        {
+            self.requires.push((line, literal.to_string()));
+        }
+    }
+}
+
+/// Renames the aliased requires of `source` to their `scope`, on their own line so the source
+/// map still matches the module
+fn rescope_requires(path: &Path, source: &str, aliases: &Aliases, scope: usize) -> Result<String> {
+    let mut block = darklua_core::Parser::default()
+        .preserve_tokens()
+        .parse(source)
+        .map_err(|e| anyhow::anyhow!("Failed to parse {}: {}", path.display(), e))?;
+    let mut collector = RequireCollector::default();
+    DefaultVisitor::visit_block(&mut block, &mut collector);
+
+    let mut lines = source
+        .split_inclusive('\n')
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    for (line, literal) in collector.requires {
+        let Some(name) = literal
+            .strip_prefix('@')
+            .and_then(|rest| rest.split('/').next())
+        else {
+            continue;
+        };
+        if let (true, Some(text)) = (aliases.contains_key(name), lines.get_mut(line - 1)) {
+            let rescoped = format!(
+                "@{}{}",
+                scoped_alias(name, scope),
+                &literal[1 + name.len()..]
+            );
+            *text = text.replacen(&literal, &rescoped, 1);
+        }
+    }
+
+    Ok(lines.concat())
+}
+
+/// The bundle resolves every require with one set of aliases, while the dependency graph and
+/// luau-lsp resolve them from the directory of each module. The modules that see other aliases
+/// than the entry file, because of a nested `.luaurc`, get their aliased requires renamed to
+/// scoped aliases pointing where their own directory resolves them
+pub fn scope_flow_aliases(
+    resources: &Resources,
+    input: &Path,
+    modules: &[PathBuf],
+    configured: &Aliases,
+) -> Result<FlowAliases> {
+    let directory = |path: &Path| path.parent().unwrap_or(Path::new("")).to_path_buf();
+    let flow_aliases = aliases_for(&directory(input), configured)?;
+
+    let mut module_scopes = Vec::new();
+    let mut scopes = BTreeMap::<Aliases, usize>::new();
+    for module in modules.iter().filter(|module| !is_data_module(module)) {
+        let module_aliases = aliases_for(&directory(module), configured)?;
+        if module_aliases != flow_aliases {
+            let next_scope = scopes.len() + 1;
+            let scope = *scopes.entry(module_aliases.clone()).or_insert(next_scope);
+            module_scopes.push((module, module_aliases, scope));
+        }
+    }
+    if module_scopes.is_empty() {
+        return Ok(FlowAliases {
+            aliases: flow_aliases,
+            sources: Vec::new(),
+        });
+    }
+
+    let mut aliases = flow_aliases;
+    for (module_aliases, scope) in &scopes {
+        for (name, target) in module_aliases {
+            aliases.insert(scoped_alias(name, *scope), target.clone());
+        }
+    }
+    let sources = modules
+        .iter()
+        .map(|module| {
+            let source = resources.get(module).map_err(|e| {
+                anyhow::anyhow!("Failed to read file {}: {:?}", module.display(), e)
+            })?;
+            match module_scopes.iter().find(|(path, ..)| *path == module) {
+                Some((_, module_aliases, scope)) => Ok((
+                    module.clone(),
+                    rescope_requires(module, &source, module_aliases, *scope)?,
+                )),
+                None => Ok((module.clone(), source)),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(FlowAliases { aliases, sources })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_strip_jsonc() {
+        let content = r#"{
+            // shared modules
+            "aliases": {
+                "shared": "./src/shared", /* trailing comma */
+                "url": "https://example.com//path",
+            },
+        }"#;
+
+        let luaurc: Luaurc = serde_json::from_str(&strip_jsonc(content)).unwrap();
+        assert_eq!(luaurc.aliases["shared"], "./src/shared");
+        assert_eq!(luaurc.aliases["url"], "https://example.com//path");
+    }
+
+    #[test]
+    fn test_rescope_requires() {
+        let source = "local http = require(\"@shared/http\")\r\n\
+                      local log = require(\"./log\")\n\
+                      local a, b = require(\"@shared/a\"), require(\"@other/b\")\n\
+                      print(\"@shared/http\")\n";
+        let aliases = Aliases::from([("shared".to_string(), PathBuf::from("src/nested/shared"))]);
+
+        assert_eq!(
+            rescope_requires(Path::new("src/nested/login.luau"), source, &aliases, 2).unwrap(),
+            "local http = require(\"@shared~2/http\")\r\n\
+             local log = require(\"./log\")\n\
+             local a, b = require(\"@shared~2/a\"), require(\"@other/b\")\n\
+             print(\"@shared/http\")\n"
+        );
+    }
+}
@@ -9,9 +9,9 @@ fn check_luau_lsp() -> Result<()> {
     Ok(())
 }
 
-pub fn analyze(config_path: &str) -> Result<()> {
+pub fn analyze(config_path: &str, profile: Option<&str>) -> Result<()> {
     check_luau_lsp()?;
-    let config = config::Config::from_file(config_path)?;
+    let config = config::Config::from_file_with_profile(config_path, profile)?;
 
     let execution_dir = env::current_dir()?;
 
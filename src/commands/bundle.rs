@@ -1,149 +1,1116 @@
-use crate::config::Flow;
+use crate::aliases::{configured_aliases, path_require_mode, scope_flow_aliases, FlowAliases};
+use crate::commands::bundle::build_info::BuildInfo;
+use crate::commands::bundle::lock::{FlowHashes, HashesLock};
+use crate::commands::bundle::manifest::{Manifest, ManifestFlow, ManifestVariant};
+use crate::commands::bundle::obfuscate::{ObfuscateBundle, SharedObfuscationMap};
+use crate::commands::bundle::reproducible::verify_reproducible;
+use crate::commands::bundle::size_report::{SizeBudget, SizeReport};
+use crate::commands::bundle::source_map::SourceMap;
+use crate::commands::bundle::validate::{validate_bundle, SharedViolations, ValidateBundle};
+use crate::commands::clean::prune;
+use crate::commands::keys::{load_signing_key, sign, signature_path};
+use crate::commands::version::dependency_graph::is_data_module;
+use crate::commands::version::sdk_version::SdkVersionOut;
+use crate::commands::version::sdk_version_folder::FoldSdkVersionChecks;
+use crate::commands::version::version_visitor::VersionFile;
+use crate::commands::version::{compute_version_for_flows, read_version_file, version_file_path};
 use crate::config::{self, SimplePlatform};
+use crate::config::{Flow, SdkVariant};
+use crate::diagnostics::Diagnostic;
 
 use anyhow::Result;
+use clap::Args;
 use darklua_core::rules::bundle::BundleRequireMode;
-use darklua_core::rules::{InjectGlobalValue, Rule};
+use darklua_core::rules::Rule;
 use darklua_core::{
     process, BundleConfiguration, Configuration, GeneratorParameters, Options, Resources,
 };
-use std::path::PathBuf;
+use ed25519_dalek::SigningKey;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 use tracing::info;
 
-fn get_global_inject_rules(platform: &SimplePlatform, flow: &Flow) -> Vec<Box<dyn Rule>> {
-    let mut rules: Vec<Box<dyn Rule>> = vec![
-        Box::new(InjectGlobalValue::string("FLOW_NAME", flow.name.clone())),
-        Box::new(InjectGlobalValue::string("FLOW_ALIAS", flow.alias.clone())),
-        Box::new(InjectGlobalValue::string(
-            "PLATFORM_NAME",
-            platform.name.clone(),
-        )),
-        Box::new(InjectGlobalValue::string(
+pub mod build_info;
+pub mod lock;
+pub mod manifest;
+pub mod obfuscate;
+pub mod reproducible;
+pub mod size_report;
+pub mod source_map;
+pub mod validate;
+
+/// `MIN_SDK_VERSION` as a number when it is a plain integer (`"10"`), as the declared string
+/// otherwise (`"1.2.3"`, `"1.10"`)
+fn sdk_version_value(min_sdk_version: &str) -> serde_json::Value {
+    match min_sdk_version.parse::<u64>() {
+        Ok(version) => serde_json::json!(version),
+        Err(_) => serde_json::json!(min_sdk_version),
+    }
+}
+
+/// Every descriptive field of the flow and its platform, injected as `FLOW_METADATA`
+fn flow_metadata(platform: &SimplePlatform, flow: &Flow) -> serde_json::Value {
+    let mut metadata = serde_json::json!({
+        "name": flow.name,
+        "alias": flow.alias,
+        "description": flow.description,
+        "path": flow.path,
+        "platform": {
+            "name": platform.name,
+            "description": platform.description,
+        },
+    });
+    if let Some(min_sdk_version) = &flow.min_sdk_version {
+        metadata["min_sdk_version"] = sdk_version_value(min_sdk_version);
+    }
+    if let Some(retrieves) = &flow.retrieves {
+        metadata["retrieves"] = serde_json::json!(retrieves);
+    }
+    metadata
+}
+
+/// Values of the built-in globals injected into every bundle of the flow, see `BUILT_IN_GLOBALS`
+pub fn built_in_global_values(
+    settings: &config::Settings,
+    platform: &SimplePlatform,
+    flow: &Flow,
+) -> BTreeMap<&'static str, serde_json::Value> {
+    let mut values = BTreeMap::from([
+        ("FLOW_NAME", serde_json::json!(flow.name)),
+        ("FLOW_ALIAS", serde_json::json!(flow.alias)),
+        ("PLATFORM_NAME", serde_json::json!(platform.name)),
+        (
             "PLATFORM_DESCRIPTION",
-            platform.description.clone(),
-        )),
-    ];
+            serde_json::json!(platform.description),
+        ),
+    ]);
+
+    let string_globals = settings.string_globals.unwrap_or(false);
 
     if let Some(min_sdk_version) = &flow.min_sdk_version {
-        rules.push(Box::new(InjectGlobalValue::string(
+        values.insert(
             "MIN_SDK_VERSION",
-            min_sdk_version.clone(),
-        )));
+            if string_globals {
+                serde_json::json!(min_sdk_version)
+            } else {
+                sdk_version_value(min_sdk_version)
+            },
+        );
     }
 
     if let Some(retrieves) = &flow.retrieves {
-        rules.push(Box::new(InjectGlobalValue::string(
+        values.insert(
             "RETRIEVES",
-            retrieves.join(", "),
-        )));
+            if string_globals {
+                serde_json::json!(retrieves.join(", "))
+            } else {
+                serde_json::json!(retrieves)
+            },
+        );
+    }
+
+    if settings.flow_metadata.unwrap_or(false) {
+        values.insert("FLOW_METADATA", flow_metadata(platform, flow));
+    }
+
+    values
+}
+
+fn get_global_inject_rules(
+    settings: &config::Settings,
+    platform: &SimplePlatform,
+    flow: &Flow,
+) -> Result<Vec<Box<dyn Rule>>> {
+    built_in_global_values(settings, platform, flow)
+        .into_iter()
+        .map(|(identifier, value)| inject_global_value(identifier, value))
+        .collect()
+}
+
+/// Creates an `inject_global_value` rule from any JSON value, tables included
+pub fn inject_global_value(identifier: &str, value: serde_json::Value) -> Result<Box<dyn Rule>> {
+    serde_json::from_value(serde_json::json!({
+        "rule": "inject_global_value",
+        "identifier": identifier,
+        "value": value,
+    }))
+    .map_err(|e| anyhow::anyhow!("Failed to inject global {}: {}", identifier, e))
+}
+
+/// Globals injected by the CLI itself, which `globals` tables can't redefine
+const BUILT_IN_GLOBALS: [&str; 10] = [
+    "FLOW_NAME",
+    "FLOW_ALIAS",
+    "PLATFORM_NAME",
+    "PLATFORM_DESCRIPTION",
+    "MIN_SDK_VERSION",
+    "RETRIEVES",
+    "FLOW_METADATA",
+    "BUILD_INFO",
+    "TARGET_SDK_VERSION",
+    "__BUNDLE_MODULES",
+];
+
+/// The `globals` of the settings, the platform and the flow, in increasing priority
+pub fn get_globals(
+    settings: &config::Settings,
+    platform: &SimplePlatform,
+    flow: &Flow,
+) -> config::Globals {
+    let mut globals = config::Globals::new();
+    for source in [&settings.globals, &platform.globals, &flow.globals]
+        .into_iter()
+        .flatten()
+    {
+        globals.extend(source.clone());
     }
+    globals
+}
+
+pub fn get_custom_global_rules(
+    settings: &config::Settings,
+    platform: &SimplePlatform,
+    flow: &Flow,
+) -> Result<Vec<Box<dyn Rule>>> {
+    get_globals(settings, platform, flow)
+        .iter()
+        .map(|(identifier, value)| {
+            if BUILT_IN_GLOBALS.contains(&identifier.as_str()) {
+                anyhow::bail!(
+                    "{}: `{}` is injected by opacity-cli and can't be redefined in `globals`",
+                    flow.alias,
+                    identifier
+                );
+            }
+            inject_global_value(identifier, serde_json::to_value(value)?)
+        })
+        .collect()
+}
+
+/// Every global the rules of a bundle inject, which must not be left in it
+fn injected_global_names(
+    settings: &config::Settings,
+    platform: &SimplePlatform,
+    flow: &Flow,
+    target: Option<&VariantTarget>,
+) -> BTreeSet<String> {
+    let mut names = built_in_global_values(settings, platform, flow)
+        .into_keys()
+        .map(str::to_string)
+        .chain(get_globals(settings, platform, flow).keys().cloned())
+        .chain(get_features(settings, flow).into_keys())
+        .collect::<BTreeSet<_>>();
+    if target.is_some() {
+        names.insert("TARGET_SDK_VERSION".to_string());
+    }
+    if settings
+        .build_info
+        .as_ref()
+        .is_some_and(|build_info| build_info.global)
+    {
+        names.insert("BUILD_INFO".to_string());
+    }
+    names
+}
+
+/// Feature flags of the flow, its own values win over the settings' (and the profile's)
+pub fn get_features(settings: &config::Settings, flow: &Flow) -> config::Features {
+    let mut features = config::Features::new();
+    for source in [&settings.features, &flow.features].into_iter().flatten() {
+        features.extend(source.clone());
+    }
+    features
+}
+
+/// Every value injected into the bundle of a flow: built-in globals, `globals` and feature
+/// flags (`BUILD_INFO` aside, it changes with every commit)
+pub fn injected_global_values(
+    settings: &config::Settings,
+    platform: &SimplePlatform,
+    flow: &Flow,
+) -> Result<BTreeMap<String, serde_json::Value>> {
+    let mut values = built_in_global_values(settings, platform, flow)
+        .into_iter()
+        .map(|(identifier, value)| (identifier.to_string(), value))
+        .collect::<BTreeMap<_, _>>();
+    for (identifier, value) in get_globals(settings, platform, flow) {
+        values.insert(identifier, serde_json::to_value(value)?);
+    }
+    for (feature, enabled) in get_features(settings, flow) {
+        values.insert(feature, serde_json::json!(enabled));
+    }
+    Ok(values)
+}
+
+/// Injects the feature flags, then folds constants and removes the branches they disable
+pub fn get_feature_rules(
+    settings: &config::Settings,
+    platform: &SimplePlatform,
+    flow: &Flow,
+) -> Result<Vec<Box<dyn Rule>>> {
+    let features = get_features(settings, flow);
+    if features.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let globals = get_globals(settings, platform, flow);
+    let mut rules = features
+        .iter()
+        .map(|(feature, enabled)| {
+            if BUILT_IN_GLOBALS.contains(&feature.as_str()) || globals.contains_key(feature) {
+                anyhow::bail!(
+                    "{}: feature `{}` has the same name as an injected global",
+                    flow.alias,
+                    feature
+                );
+            }
+            inject_global_value(feature, serde_json::json!(enabled))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    rules.extend(get_dead_branch_rules()?);
+    Ok(rules)
+}
+
+/// Folds constant expressions, then removes the `if` branches that can't run anymore
+fn get_dead_branch_rules() -> Result<Vec<Box<dyn Rule>>> {
+    ["compute_expression", "remove_unused_if_branch"]
+        .into_iter()
+        .map(|rule| Ok(serde_json::from_value(serde_json::json!(rule))?))
+        .collect()
+}
+
+/// A bundle of a flow for an SDK range, see `Flow::variants`
+pub struct VariantTarget<'a> {
+    pub variant: &'a SdkVariant,
+    /// `sdkVersionFunction` of version_file.json
+    pub sdk_version_function: &'a str,
+}
 
-    rules
+/// Name of a variant in file names and hashes.lock: `<alias>@<range>`
+pub fn variant_alias(alias: &str, variant: &SdkVariant) -> String {
+    format!("{}@{}", alias, variant.range())
+}
+
+/// Aliases of the bundles of a flow: its own, then one per SDK variant
+pub fn bundle_aliases(flow: &Flow) -> Vec<String> {
+    std::iter::once(flow.alias.clone())
+        .chain(
+            flow.variants
+                .iter()
+                .flatten()
+                .map(|variant| variant_alias(&flow.alias, variant)),
+        )
+        .collect()
+}
+
+/// `sdkVersionFunction` of version_file.json, which the variants of a flow can't do without
+pub fn variant_sdk_version_function<'a>(
+    flow: &Flow,
+    version_file: Option<&'a VersionFile>,
+) -> Result<&'a str> {
+    version_file
+        .map(|version_file| version_file.sdk_version_function.as_str())
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "{}: SDK variants need a version_file.json with a sdkVersionFunction",
+                flow.alias
+            )
+        })
+}
+
+/// Injects `TARGET_SDK_VERSION` (the lowest SDK version of the range) and strips the
+/// branches of the SDK version checks that never run in the range
+fn get_variant_rules(target: &VariantTarget) -> Result<Vec<Box<dyn Rule>>> {
+    let mut rules = vec![
+        inject_global_value(
+            "TARGET_SDK_VERSION",
+            serde_json::json!(target.variant.min.unwrap_or(0)),
+        )?,
+        Box::new(FoldSdkVersionChecks::new(
+            target.sdk_version_function,
+            target.variant,
+        )) as Box<dyn Rule>,
+    ];
+    rules.extend(get_dead_branch_rules()?);
+    Ok(rules)
+}
+
+/// The configured darklua generator, dense with a column span of 80 by default
+fn get_generator(settings: &config::Settings) -> Result<GeneratorParameters> {
+    match &settings.generator {
+        None => Ok(GeneratorParameters::Dense { column_span: 80 }),
+        Some(toml::Value::String(name)) => name
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid generator {}: {}", name, e)),
+        Some(generator) => generator
+            .clone()
+            .try_into()
+            .map_err(|e| anyhow::anyhow!("Invalid generator: {}", e)),
+    }
+}
+
+/// The flow's obfuscation settings, or `[settings]`' if it has none
+pub fn get_obfuscation<'a>(
+    settings: &'a config::Settings,
+    flow: &'a Flow,
+) -> Option<&'a config::ObfuscationSettings> {
+    flow.obfuscation
+        .as_ref()
+        .or(settings.obfuscation.as_ref())
+        .filter(|obfuscation| obfuscation.enabled)
+}
+
+/// Collects the user-configured darklua rules, in order: settings, then platform, then flow
+pub fn get_configured_rules(
+    config: &config::Config,
+    platform: &SimplePlatform,
+    flow: &Flow,
+) -> Result<Vec<Box<dyn Rule>>> {
+    config
+        .settings
+        .rules
+        .iter()
+        .chain(platform.rules.iter())
+        .chain(flow.rules.iter())
+        .flatten()
+        .map(|rule| {
+            rule.clone().try_into::<Box<dyn Rule>>().map_err(|e| {
+                anyhow::anyhow!(
+                    "Invalid rule for flow {} ({}): {}",
+                    flow.name,
+                    flow.alias,
+                    e
+                )
+            })
+        })
+        .collect()
 }
 
 pub fn process_bundle(resources: &Resources, options: Options) -> Result<()> {
     let process_start = Instant::now();
-    let result =
-        process(resources, options).map_err(|e| anyhow::anyhow!("Processing failed: {:?}", e))?;
+    let result = process(resources, options).map_err(|e| {
+        Diagnostic::from_darklua_error(&e).emit();
+        anyhow::anyhow!("Processing failed")
+    })?;
 
     match result.result() {
         Ok(_) => {
             println!("Successfully processed in {:?}", process_start.elapsed());
             Ok(())
         }
-        Err(err) => {
-            anyhow::bail!("Failed to process: {:?}", err);
+        Err(errors) => {
+            for error in &errors {
+                Diagnostic::from_darklua_error(error).emit();
+            }
+            anyhow::bail!("Failed to process: {} error(s)", errors.len());
         }
     }
 }
 
-fn compute_hashes(file_paths: &mut Vec<PathBuf>) -> Result<Vec<(String, String)>> {
-    file_paths.sort();
+/// A finished bundle that hasn't replaced the previous one yet, it's removed unless committed
+pub struct StagedBundle {
+    output: PathBuf,
+    staging_output: PathBuf,
+    /// What the obfuscation pass renamed
+    pub obfuscation: Option<SharedObfuscationMap>,
+}
+
+impl StagedBundle {
+    /// Moves the bundle to its output path, replacing the previous one
+    pub fn commit(self) -> Result<PathBuf> {
+        std::fs::rename(&self.staging_output, &self.output)?;
+        Ok(self.output.clone())
+    }
+}
+
+impl Drop for StagedBundle {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.staging_output);
+    }
+}
+
+/// Processes a flow's bundle options and finishes the bundle, without touching the previous one
+pub fn stage_flow(resources: &Resources, bundle_options: BundleOptions) -> Result<StagedBundle> {
+    let BundleOptions {
+        opts,
+        output,
+        staging_output,
+        banner,
+        obfuscation,
+        sources,
+        ..
+    } = bundle_options;
+    let staged = StagedBundle {
+        output,
+        staging_output,
+        obfuscation,
+    };
+
+    if sources.is_empty() {
+        process_bundle(resources, opts)?;
+    } else {
+        // darklua reads every module through the resources, the rescoped ones only exist here
+        let memory = Resources::from_memory();
+        for (path, source) in &sources {
+            memory
+                .write(path, source)
+                .map_err(|e| anyhow::anyhow!("Failed to stage {}: {:?}", path.display(), e))?;
+        }
+        process_bundle(&memory, opts)?;
+        let content = memory.get(&staged.staging_output).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to read {}: {:?}",
+                staged.staging_output.display(),
+                e
+            )
+        })?;
+        std::fs::write(&staged.staging_output, content)?;
+    }
+
+    if let Some(banner) = &banner {
+        let content = std::fs::read_to_string(&staged.staging_output)?;
+        std::fs::write(&staged.staging_output, format!("{}\n{}", banner, content))?;
+    }
+
+    Ok(staged)
+}
+
+/// Processes a flow's bundle options and finishes the output, returns the bundle path
+pub fn build_flow(resources: &Resources, bundle_options: BundleOptions) -> Result<PathBuf> {
+    stage_flow(resources, bundle_options)?.commit()
+}
+
+/// Stages a bundle for `bundle`, which only replaces the previous one if it passes validation
+fn stage_validated_flow(
+    resources: &Resources,
+    bundle_options: BundleOptions,
+) -> Result<StagedBundle> {
+    let violations = bundle_options.violations.clone();
+    let staged = stage_flow(resources, bundle_options)?;
+    validate_bundle(
+        &std::fs::read_to_string(&staged.staging_output)?,
+        &staged.output,
+        &violations.lock().unwrap(),
+    )?;
+    Ok(staged)
+}
+
+/// Where the source map of a bundle lives: `<alias>.bundle.luau.map`
+pub fn source_map_path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".map");
+    PathBuf::from(path)
+}
+
+/// Paths as they appear in source maps and the manifest, relative and with forward slashes
+pub fn display_path(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+pub fn sha256_hex(content: &[u8]) -> String {
     use sha2::Digest;
+    format!("{:x}", sha2::Sha256::digest(content))
+}
+
+/// What a flow is made of, according to the dependency graph
+pub struct FlowModules {
+    /// Every module embedded in the bundle, the flow itself first
+    pub modules: Vec<PathBuf>,
+    /// Only computed when a version file is available
+    pub sdk_version: Option<SdkVersionOut>,
+    pub aliases: FlowAliases,
+}
+
+pub fn collect_flow_modules(
+    resources: &Resources,
+    settings: &config::Settings,
+    flow: &Flow,
+    version_file: Option<&VersionFile>,
+) -> Result<FlowModules> {
+    let input = PathBuf::from(&flow.path);
+    let configured = configured_aliases(settings);
+    let work = compute_version_for_flows(
+        resources,
+        vec![input.clone()],
+        version_file.cloned().unwrap_or_default(),
+        &configured,
+    )?;
+    let modules = work.module_closure(&input);
+    let aliases = scope_flow_aliases(resources, &input, &modules, &configured)?;
+
+    Ok(FlowModules {
+        modules,
+        sdk_version: version_file.and(work.get_versions().into_values().next()),
+        aliases,
+    })
+}
+
+/// Writes the source map of a freshly bundled flow, mapping it back to every module it embeds
+pub fn write_source_map(
+    resources: &Resources,
+    flow_modules: &FlowModules,
+    output: &Path,
+    obfuscation: Option<&SharedObfuscationMap>,
+) -> Result<SourceMap> {
+    let generated = std::fs::read_to_string(output)?;
+    let source_map = generate_source_map(resources, flow_modules, output, &generated, obfuscation)?;
+    std::fs::write(source_map_path(output), serde_json::to_string(&source_map)?)?;
+
+    Ok(source_map)
+}
+
+/// Source map of the `generated` bundle of a flow, to be written to `output`
+fn generate_source_map(
+    resources: &Resources,
+    flow_modules: &FlowModules,
+    output: &Path,
+    generated: &str,
+    obfuscation: Option<&SharedObfuscationMap>,
+) -> Result<SourceMap> {
+    // data modules are converted to Lua values, their lines can't be mapped
+    let sources = flow_modules
+        .modules
+        .iter()
+        .filter(|path| !is_data_module(path))
+        .map(|path| {
+            let content = resources
+                .get(path)
+                .map_err(|e| anyhow::anyhow!("Failed to read file {}: {:?}", path.display(), e))?;
+            Ok((display_path(path), content))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let file_name = output
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let obfuscation = obfuscation.map(|obfuscation| obfuscation.lock().unwrap().clone());
+    Ok(SourceMap::generate(
+        &file_name,
+        generated,
+        &sources,
+        obfuscation.as_ref(),
+    ))
+}
 
-    let mut hashes: Vec<(String, String)> = Vec::new();
-    for file_path in file_paths {
-        let file_content = std::fs::read(file_path.clone())?;
-        let hash = format!("{:x}", sha2::Sha256::digest(&file_content));
-        hashes.push((file_path.to_string_lossy().to_string(), hash));
+/// A validated bundle with everything `bundle` writes next to it, computed from the staged
+/// bundle so nothing is left to fail once the previous bundle starts being replaced
+struct PreparedBundle {
+    staged: StagedBundle,
+    content: String,
+    source_map: SourceMap,
+    size_report: SizeReport,
+    signature: Option<String>,
+}
+
+impl PreparedBundle {
+    fn new(
+        resources: &Resources,
+        flow_modules: &FlowModules,
+        signing_key: Option<&SigningKey>,
+        staged: StagedBundle,
+    ) -> Result<Self> {
+        let content = std::fs::read_to_string(&staged.staging_output)?;
+        let source_map = generate_source_map(
+            resources,
+            flow_modules,
+            &staged.output,
+            &content,
+            staged.obfuscation.as_ref(),
+        )?;
+        let size_report = SizeReport::new(&content, &source_map)?;
+        let signature = signing_key.map(|signing_key| sign(signing_key, content.as_bytes()));
+
+        Ok(Self {
+            staged,
+            content,
+            source_map,
+            size_report,
+            signature,
+        })
+    }
+
+    /// Writes the source map and the detached signature, or removes the one left by an
+    /// earlier signed build, then moves the bundle to its output path
+    fn commit(self) -> Result<()> {
+        let output = &self.staged.output;
+        std::fs::write(
+            source_map_path(output),
+            serde_json::to_string(&self.source_map)?,
+        )?;
+        let signature = signature_path(output);
+        match &self.signature {
+            Some(content) => std::fs::write(&signature, content)?,
+            None if signature.exists() => std::fs::remove_file(&signature)?,
+            None => {}
+        }
+        self.staged.commit()?;
+        Ok(())
     }
+}
+
+#[derive(Args, Debug, Default)]
+pub struct BundleArgs {
+    /// Sign every bundle with this Ed25519 private key (see `keys generate`)
+    #[arg(long, value_name = "KEY")]
+    pub sign: Option<String>,
 
-    Ok(hashes)
+    /// Bundle every flow twice, in shuffled order, and check that the outputs are identical
+    /// (the bundles already in the output directory are left untouched)
+    #[arg(long)]
+    pub verify_reproducible: bool,
+
+    /// Bundle every flow even if some fail, then report all the failures together
+    /// (hashes.lock is only updated for the flows that succeeded)
+    #[arg(long)]
+    pub keep_going: bool,
+
+    /// Delete bundles, source maps and signatures in the output directory that don't belong
+    /// to any configured flow
+    #[arg(long)]
+    pub prune: bool,
 }
 
 pub struct BundleOptions {
     pub opts: Options,
     pub output: PathBuf,
+    /// Where darklua writes the bundle, it's moved to `output` once finished
+    pub staging_output: PathBuf,
+    /// Comment prepended to the bundle once darklua is done
+    pub banner: Option<String>,
+    /// What the obfuscation pass renamed, once darklua is done
+    pub obfuscation: Option<SharedObfuscationMap>,
+    /// What the validation pass found wrong with the bundle, once darklua is done
+    pub violations: SharedViolations,
+    /// Modules to bundle from memory instead of the resources, see `FlowAliases::sources`
+    pub sources: Vec<(PathBuf, String)>,
 }
 
 pub fn create_options(
     config: &config::Config,
     platform: &SimplePlatform,
     flow: &Flow,
+    flow_modules: &FlowModules,
 ) -> Result<BundleOptions> {
-    std::fs::create_dir_all(&config.settings.output_directory)?;
+    create_options_in(
+        config,
+        platform,
+        flow,
+        flow_modules,
+        Path::new(&config.settings.output_directory),
+        None,
+    )
+}
+
+pub fn create_variant_options(
+    config: &config::Config,
+    platform: &SimplePlatform,
+    flow: &Flow,
+    flow_modules: &FlowModules,
+    target: &VariantTarget,
+) -> Result<BundleOptions> {
+    create_options_in(
+        config,
+        platform,
+        flow,
+        flow_modules,
+        Path::new(&config.settings.output_directory),
+        Some(target),
+    )
+}
+
+/// Same as `create_options`, but bundles into `output_directory` instead of the configured one
+pub fn create_options_in(
+    config: &config::Config,
+    platform: &SimplePlatform,
+    flow: &Flow,
+    flow_modules: &FlowModules,
+    output_directory: &Path,
+    target: Option<&VariantTarget>,
+) -> Result<BundleOptions> {
+    std::fs::create_dir_all(output_directory)?;
     let input = PathBuf::from(&flow.path);
 
-    let output = PathBuf::from(&config.settings.output_directory)
-        .join(format!("{}.bundle.luau", flow.alias));
+    let name = match target {
+        Some(target) => variant_alias(&flow.alias, target.variant),
+        None => flow.alias.clone(),
+    };
+    let output = output_directory.join(format!("{}.bundle.luau", name));
+    let staging_output = output_directory.join(format!("{}.bundle.luau.tmp", name));
+
+    let mut rules = get_global_inject_rules(&config.settings, platform, flow)?;
+    rules.extend(get_custom_global_rules(&config.settings, platform, flow)?);
+    rules.extend(get_feature_rules(&config.settings, platform, flow)?);
+    if let Some(target) = target {
+        rules.extend(get_variant_rules(target)?);
+    }
+
+    let build_info = match &config.settings.build_info {
+        Some(settings) => Some((settings, BuildInfo::new(settings, flow_modules)?)),
+        None => None,
+    };
+    if let Some((settings, build_info)) = &build_info {
+        if settings.global {
+            rules.push(inject_global_value("BUILD_INFO", build_info.to_json())?);
+        }
+    }
+
+    rules.extend(get_configured_rules(config, platform, flow)?);
+    // before the obfuscation, which hides module keys and strings
+    let validation = ValidateBundle::new(injected_global_names(
+        &config.settings,
+        platform,
+        flow,
+        target,
+    ));
+    let violations = validation.violations();
+    rules.push(Box::new(validation));
+    // last, so no other rule sees the obfuscated names
+    let obfuscation = get_obfuscation(&config.settings, flow).map(|settings| {
+        let rule = ObfuscateBundle::new(settings, &name);
+        let map = rule.map();
+        rules.push(Box::new(rule));
+        map
+    });
+    let generator = get_generator(&config.settings)?;
 
     let mut config = Configuration::empty();
     config = config.with_bundle_configuration(
-        BundleConfiguration::new(BundleRequireMode::Path(Default::default()))
-            .with_modules_identifier("__BUNDLE_MODULES"),
+        BundleConfiguration::new(BundleRequireMode::Path(path_require_mode(
+            &flow_modules.aliases.aliases,
+        )?))
+        .with_modules_identifier("__BUNDLE_MODULES"),
     );
 
-    let rules = get_global_inject_rules(platform, flow);
-
     for rule in rules {
         config = config.with_rule(rule);
     }
 
     Ok(BundleOptions {
         opts: Options::new(&input)
-            .with_output(&output)
-            .with_generator_override(GeneratorParameters::Dense { column_span: 80 })
+            .with_output(&staging_output)
+            .with_generator_override(generator)
             .with_configuration(config),
-        output: output.clone(),
+        output,
+        staging_output,
+        banner: build_info
+            .filter(|(settings, _)| settings.banner)
+            .map(|(_, build_info)| build_info.banner()),
+        obfuscation,
+        violations,
+        sources: flow_modules.aliases.sources.clone(),
+    })
+}
+
+/// What `bundle` needs to bundle each flow
+struct BundleContext<'a> {
+    config: &'a config::Config,
+    resources: &'a Resources,
+    version_file: Option<&'a VersionFile>,
+    signing_key: Option<&'a SigningKey>,
+    config_dir: &'a Path,
+    previous_manifest: Option<&'a Manifest>,
+}
+
+/// Everything a successfully bundled flow contributes to the manifest and hashes.lock
+struct BundledFlow {
+    manifest_flow: ManifestFlow,
+    /// Hashes of the flow and of its variants, keyed by alias
+    hashes: Vec<(String, FlowHashes)>,
+    budget_errors: Vec<String>,
+}
+
+fn bundle_flow(
+    context: &BundleContext,
+    platform: &SimplePlatform,
+    flow: &Flow,
+) -> Result<BundledFlow> {
+    let BundleContext {
+        config,
+        resources,
+        version_file,
+        signing_key,
+        config_dir,
+        previous_manifest,
+    } = context;
+
+    println!("Bundling {} ({})", flow.name, flow.alias);
+    let features = get_features(&config.settings, flow);
+    if !features.is_empty() {
+        println!(
+            "  Features: {}",
+            features
+                .iter()
+                .map(|(feature, enabled)| format!(
+                    "{}={}",
+                    feature,
+                    if *enabled { "on" } else { "off" }
+                ))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    let flow_modules = collect_flow_modules(resources, &config.settings, flow, *version_file)?;
+
+    // every bundle of the flow is validated and prepared before any of them replaces the
+    // previous one, so a failure leaves the flow as the previous build wrote it
+    let prepared = PreparedBundle::new(
+        resources,
+        &flow_modules,
+        *signing_key,
+        stage_validated_flow(
+            resources,
+            create_options(config, platform, flow, &flow_modules)?,
+        )?,
+    )?;
+    let mut prepared_variants = Vec::new();
+    for variant in flow.variants.iter().flatten() {
+        println!("Bundling variant {}", variant_alias(&flow.alias, variant));
+        let target = VariantTarget {
+            variant,
+            sdk_version_function: variant_sdk_version_function(flow, *version_file)?,
+        };
+        let bundle_options =
+            create_variant_options(config, platform, flow, &flow_modules, &target)?;
+        prepared_variants.push((
+            variant,
+            PreparedBundle::new(
+                resources,
+                &flow_modules,
+                *signing_key,
+                stage_validated_flow(resources, bundle_options)?,
+            )?,
+        ));
+    }
+
+    prepared.size_report.print();
+    let budget = SizeBudget::new(config, platform, flow);
+    let previous_flow =
+        previous_manifest.and_then(|previous_manifest| previous_manifest.get_flow(&flow.alias));
+    let mut budget_errors = Vec::from_iter(budget.check(
+        flow,
+        &flow.alias,
+        &prepared.size_report,
+        previous_flow.map(|previous_flow| previous_flow.size),
+    ));
+
+    let mut manifest_flow = ManifestFlow::new(
+        config_dir,
+        platform,
+        flow,
+        &flow_modules,
+        &prepared.staged.output,
+        prepared.content.as_bytes(),
+        &prepared.size_report,
+    );
+    manifest_flow.signature = prepared.signature.clone();
+    manifest_flow.features = features;
+    let mut hashes = vec![(
+        flow.alias.clone(),
+        FlowHashes::new(
+            config_dir,
+            &flow_modules,
+            &prepared.staged.output,
+            prepared.content.as_bytes(),
+        )?,
+    )];
+
+    for (variant, prepared) in &prepared_variants {
+        prepared.size_report.print();
+        // a variant has the same budget as its flow
+        let alias = variant_alias(&flow.alias, variant);
+        budget_errors.extend(
+            budget.check(
+                flow,
+                &alias,
+                &prepared.size_report,
+                previous_flow
+                    .and_then(|previous_flow| {
+                        previous_flow.variants.iter().find(|previous_variant| {
+                            previous_variant.min_sdk_version == variant.min
+                                && previous_variant.max_sdk_version == variant.max
+                        })
+                    })
+                    .map(|previous_variant| previous_variant.size),
+            ),
+        );
+
+        let mut manifest_variant = ManifestVariant::new(
+            variant,
+            &prepared.staged.output,
+            prepared.content.as_bytes(),
+            &prepared.size_report,
+        );
+        manifest_variant.signature = prepared.signature.clone();
+        manifest_flow.variants.push(manifest_variant);
+        hashes.push((
+            alias,
+            FlowHashes::new(
+                config_dir,
+                &flow_modules,
+                &prepared.staged.output,
+                prepared.content.as_bytes(),
+            )?,
+        ));
+    }
+
+    prepared.commit()?;
+    for (_, prepared) in prepared_variants {
+        prepared.commit()?;
+    }
+
+    Ok(BundledFlow {
+        manifest_flow,
+        hashes,
+        budget_errors,
     })
 }
 
-pub fn bundle(config_path: &str, is_rebundle: bool) -> Result<()> {
-    let config = config::Config::from_file(config_path)?;
+pub fn bundle(
+    config_path: &str,
+    profile: Option<&str>,
+    is_rebundle: bool,
+    args: &BundleArgs,
+) -> Result<()> {
+    let config = config::Config::from_file_with_profile(config_path, profile)?;
     let resources = Resources::from_file_system();
 
+    let version_file = if version_file_path(config_path).exists() {
+        Some(read_version_file(config_path)?)
+    } else {
+        None
+    };
+
+    if args.verify_reproducible {
+        return verify_reproducible(&config, &resources, version_file.as_ref());
+    }
+
+    let signing_key = args.sign.as_deref().map(load_signing_key).transpose()?;
+
     std::fs::create_dir_all(&config.settings.output_directory)?;
 
-    let mut file_paths: Vec<PathBuf> = Vec::new();
+    let mut config_path_dir_buf = PathBuf::from(config_path);
+    config_path_dir_buf.pop();
+    let previous_lock = HashesLock::read(&config_path_dir_buf, profile)?;
+
+    let previous_manifest = Manifest::read(&config.settings.output_directory)?;
+
+    let context = BundleContext {
+        config: &config,
+        resources: &resources,
+        version_file: version_file.as_ref(),
+        signing_key: signing_key.as_ref(),
+        config_dir: &config_path_dir_buf,
+        previous_manifest: previous_manifest.as_ref(),
+    };
+
+    let mut lock = HashesLock::default();
+    let mut manifest = Manifest {
+        profile: config.profile.clone(),
+        ..Manifest::new()
+    };
+    let mut budget_errors = Vec::new();
+    let mut bundled = Vec::new();
+    let mut failures = Vec::new();
 
     for platform in &config.platforms {
         println!("Processing platform: {}", platform.name);
         let simple_platform = SimplePlatform::from(platform);
 
         for flow in &platform.flows {
-            println!("Bundling {} ({})", flow.name, flow.alias);
+            match bundle_flow(&context, &simple_platform, flow) {
+                Ok(bundled_flow) => {
+                    budget_errors.extend(bundled_flow.budget_errors);
+                    lock.flows.extend(bundled_flow.hashes);
+                    manifest.flows.push(bundled_flow.manifest_flow);
+                    bundled.push(flow);
+                }
+                Err(err) if args.keep_going => {
+                    println!("Failed to bundle {} ({}): {:#}", flow.name, flow.alias, err);
+                    // the flow keeps what the previous bundle recorded
+                    if let Some(previous_lock) = &previous_lock {
+                        lock.flows.extend(
+                            previous_lock
+                                .flows
+                                .iter()
+                                .filter(|(alias, _)| {
+                                    *alias == &flow.alias
+                                        || alias.starts_with(&format!("{}@", flow.alias))
+                                })
+                                .map(|(alias, hashes)| (alias.clone(), hashes.clone())),
+                        );
+                    }
+                    if let Some(previous_flow) = previous_manifest
+                        .as_ref()
+                        .and_then(|previous_manifest| previous_manifest.get_flow(&flow.alias))
+                    {
+                        manifest.flows.push(previous_flow.clone());
+                    }
+                    failures.push((flow, err));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
 
-            let bundle_options = create_options(&config, &simple_platform, flow)?;
+    if args.keep_going {
+        println!();
+        println!(
+            "Bundled {} flow(s), {} failed",
+            bundled.len(),
+            failures.len()
+        );
+        for flow in &bundled {
+            println!("  OK      {} ({})", flow.name, flow.alias);
+        }
+        for (flow, err) in &failures {
+            println!("  FAILED  {} ({}): {:#}", flow.name, flow.alias, err);
+        }
+    }
 
-            file_paths.push(bundle_options.output.clone());
+    manifest.write(&config.settings.output_directory)?;
 
-            process_bundle(&resources, bundle_options.opts)?;
+    if let Some(previous_lock) = previous_lock {
+        let changed = lock
+            .flows
+            .iter()
+            .filter(|(alias, hashes)| {
+                previous_lock
+                    .flows
+                    .get(*alias)
+                    .is_none_or(|previous| previous.hash != hashes.hash)
+            })
+            .map(|(alias, _)| alias.as_str())
+            .collect::<Vec<_>>();
+        if !changed.is_empty() {
+            info!(
+                "Bundles changed since the last hashes.lock: {}",
+                changed.join(", ")
+            );
         }
     }
+    lock.write(&config_path_dir_buf, profile)?;
 
-    let hashes = compute_hashes(&mut file_paths)?;
+    if args.prune {
+        let pruned = prune(&config)?;
+        info!("Pruned {} stale output file(s)", pruned);
+    }
 
-    let mut config_path_dir_buf = PathBuf::from(config_path);
-    config_path_dir_buf.pop();
-    std::fs::write(
-        config_path_dir_buf.join("hashes.lock"),
-        hashes
-            .iter()
-            .map(|(path, hash)| format!("{}:{}", path, hash))
-            .collect::<Vec<String>>()
-            .join("\n"),
-    )?;
+    // only fail once the manifest and hashes.lock describe the bundles already written
+    if !budget_errors.is_empty() {
+        anyhow::bail!(
+            "Bundle size budget exceeded:\n  {}",
+            budget_errors.join("\n  ")
+        );
+    }
+
+    if !failures.is_empty() {
+        anyhow::bail!("{} flow(s) failed to bundle", failures.len());
+    }
 
     if is_rebundle {
         info!("Rebundled all flows successfully");
@@ -153,3 +1120,322 @@ pub fn bundle(config_path: &str, is_rebundle: bool) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::paths::TestDirectory;
+
+    #[test]
+    fn test_sdk_version_value() {
+        assert_eq!(sdk_version_value("10"), serde_json::json!(10));
+        assert_eq!(sdk_version_value("1.10"), serde_json::json!("1.10"));
+        assert_eq!(sdk_version_value("1e3"), serde_json::json!("1e3"));
+        assert_eq!(sdk_version_value("1.2.3"), serde_json::json!("1.2.3"));
+    }
+
+    #[test]
+    fn test_injected_global_names_only_hold_what_the_rules_inject() {
+        let config: config::Config = toml::from_str(
+            r#"
+            [settings]
+            output_directory = "out"
+            globals = { API_URL = "https://example.com" }
+            features = { NEW_LOGIN = true }
+
+            [[platforms]]
+            name = "example"
+            description = "Example platform"
+
+            [[platforms.flows]]
+            name = "Example"
+            alias = "example"
+            description = "Example flow"
+            path = "flow.luau"
+            variants = [{ min = 26 }]
+            "#,
+        )
+        .unwrap();
+        let platform = SimplePlatform::from(&config.platforms[0]);
+        let flow = &config.platforms[0].flows[0];
+        let target = VariantTarget {
+            variant: &flow.variants.as_ref().unwrap()[0],
+            sdk_version_function: "getSdkVersion",
+        };
+
+        let fallback = injected_global_names(&config.settings, &platform, flow, None);
+        let variant = injected_global_names(&config.settings, &platform, flow, Some(&target));
+        for identifier in ["FLOW_ALIAS", "API_URL", "NEW_LOGIN"] {
+            assert!(fallback.contains(identifier));
+            assert!(variant.contains(identifier));
+        }
+        // neither `build_info` nor `min_sdk_version` is configured
+        assert!(!fallback.contains("BUILD_INFO"));
+        assert!(!fallback.contains("MIN_SDK_VERSION"));
+        assert!(!fallback.contains("TARGET_SDK_VERSION"));
+        assert!(variant.contains("TARGET_SDK_VERSION"));
+    }
+
+    #[test]
+    fn test_obfuscated_bundle_is_validated() {
+        let directory = TestDirectory::create();
+        let flow_path = directory.join("flow.luau");
+        std::fs::write(&flow_path, "print(\"/opt/build/src/shared/http.luau\")\n").unwrap();
+
+        let config: config::Config = toml::from_str(&format!(
+            r#"
+            [settings]
+            output_directory = '{}'
+            obfuscation = {{}}
+
+            [[platforms]]
+            name = "example"
+            description = "Example platform"
+
+            [[platforms.flows]]
+            name = "Example"
+            alias = "example"
+            description = "Example flow"
+            path = '{}'
+            "#,
+            display_path(&directory.join("out")),
+            display_path(&flow_path)
+        ))
+        .unwrap();
+        let platform = SimplePlatform::from(&config.platforms[0]);
+        let flow = &config.platforms[0].flows[0];
+
+        let resources = Resources::from_file_system();
+        let result = collect_flow_modules(&resources, &config.settings, flow, None).and_then(
+            |flow_modules| {
+                stage_validated_flow(
+                    &resources,
+                    create_options(&config, &platform, flow, &flow_modules)?,
+                )
+                .map(|_| ())
+            },
+        );
+        let output_exists = directory.join("out/example.bundle.luau").exists();
+
+        // the encoded string still leaks the path, and nothing replaced the previous bundle
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("failed validation"));
+        assert!(!output_exists);
+    }
+
+    #[test]
+    fn test_bundle_flow_with_variants_reading_target_sdk_version() {
+        let directory = TestDirectory::create();
+        let flow_path = directory.join("flow.luau");
+        std::fs::write(
+            &flow_path,
+            "if TARGET_SDK_VERSION and TARGET_SDK_VERSION >= 26 then\n\tprint(\"fast\")\nelse\n\tprint(\"legacy\")\nend\n",
+        )
+        .unwrap();
+
+        let config: config::Config = toml::from_str(&format!(
+            r#"
+            [settings]
+            output_directory = '{}'
+
+            [[platforms]]
+            name = "example"
+            description = "Example platform"
+
+            [[platforms.flows]]
+            name = "Example"
+            alias = "example"
+            description = "Example flow"
+            path = '{}'
+            variants = [{{ min = 26 }}]
+            "#,
+            display_path(&directory.join("out")),
+            display_path(&flow_path)
+        ))
+        .unwrap();
+        let platform = SimplePlatform::from(&config.platforms[0]);
+        let flow = &config.platforms[0].flows[0];
+        let target = VariantTarget {
+            variant: &flow.variants.as_ref().unwrap()[0],
+            sdk_version_function: "getSdkVersion",
+        };
+
+        let resources = Resources::from_file_system();
+        let bundles = collect_flow_modules(&resources, &config.settings, flow, None).and_then(
+            |flow_modules| {
+                let fallback = stage_validated_flow(
+                    &resources,
+                    create_options(&config, &platform, flow, &flow_modules)?,
+                )?
+                .commit()?;
+                let variant = stage_validated_flow(
+                    &resources,
+                    create_variant_options(&config, &platform, flow, &flow_modules, &target)?,
+                )?
+                .commit()?;
+                Ok((
+                    std::fs::read_to_string(fallback)?,
+                    std::fs::read_to_string(variant)?,
+                ))
+            },
+        );
+
+        let (fallback, variant) = bundles.unwrap();
+        assert!(fallback.contains("TARGET_SDK_VERSION"));
+        assert!(fallback.contains("legacy"));
+        assert!(!variant.contains("TARGET_SDK_VERSION"));
+    }
+
+    #[test]
+    fn test_keep_going_keeps_what_failed_flows_recorded() -> Result<()> {
+        let directory = TestDirectory::create();
+        let config_path = directory.join("opacity.toml");
+        let output_directory = directory.join("out");
+        std::fs::write(directory.join("login.luau"), "print(\"login\")\n")?;
+        std::fs::write(directory.join("signup.luau"), "print(\"signup\")\n")?;
+        std::fs::write(
+            &config_path,
+            format!(
+                r#"
+                [settings]
+                output_directory = '{}'
+
+                [[platforms]]
+                name = "example"
+                description = "Example platform"
+
+                [[platforms.flows]]
+                name = "Login"
+                alias = "login"
+                description = "Login flow"
+                path = '{}'
+
+                [[platforms.flows]]
+                name = "Signup"
+                alias = "signup"
+                description = "Signup flow"
+                path = '{}'
+                "#,
+                display_path(&output_directory),
+                display_path(&directory.join("login.luau")),
+                display_path(&directory.join("signup.luau"))
+            ),
+        )?;
+        let config_path = config_path.to_string_lossy().to_string();
+        let args = BundleArgs {
+            keep_going: true,
+            ..Default::default()
+        };
+
+        bundle(&config_path, None, false, &args)?;
+        let signup_bundle = std::fs::read(output_directory.join("signup.bundle.luau"))?;
+        let signup_hashes = HashesLock::read(&directory, None)?.unwrap().flows["signup"].clone();
+
+        std::fs::write(directory.join("login.luau"), "print(\"login v2\")\n")?;
+        std::fs::write(directory.join("signup.luau"), "print(\"signup\"\n")?;
+        let error = bundle(&config_path, None, false, &args).unwrap_err();
+        assert_eq!(error.to_string(), "1 flow(s) failed to bundle");
+
+        // the flow that succeeded is updated, the one that failed is left as it was
+        let login_bundle = std::fs::read_to_string(output_directory.join("login.bundle.luau"))?;
+        assert!(login_bundle.contains("login v2"));
+        assert_eq!(
+            std::fs::read(output_directory.join("signup.bundle.luau"))?,
+            signup_bundle
+        );
+        let lock = HashesLock::read(&directory, None)?.unwrap();
+        assert_eq!(lock.flows["signup"].hash, signup_hashes.hash);
+        assert_eq!(
+            lock.flows["login"].hash,
+            sha256_hex(login_bundle.as_bytes())
+        );
+        let manifest = Manifest::read(&output_directory.to_string_lossy())?.unwrap();
+        assert_eq!(
+            manifest.get_flow("signup").unwrap().sha256,
+            signup_hashes.hash
+        );
+        Ok(())
+    }
+
+    /// Bundles `source` as the only flow of a config with these `[settings]`, returns the bundle
+    fn bundle_source(directory: &Path, settings: &str, source: &str) -> Result<String> {
+        let flow_path = directory.join("flow.luau");
+        std::fs::write(&flow_path, source)?;
+        let config: config::Config = toml::from_str(&format!(
+            r#"
+            [settings]
+            output_directory = '{}'
+            {}
+
+            [[platforms]]
+            name = "example"
+            description = "Example platform"
+
+            [[platforms.flows]]
+            name = "Example"
+            alias = "example"
+            description = "Example flow"
+            path = '{}'
+            "#,
+            display_path(&directory.join("out")),
+            settings,
+            display_path(&flow_path)
+        ))?;
+        let platform = SimplePlatform::from(&config.platforms[0]);
+        let flow = &config.platforms[0].flows[0];
+
+        let resources = Resources::from_file_system();
+        let flow_modules = collect_flow_modules(&resources, &config.settings, flow, None)?;
+        let output = stage_validated_flow(
+            &resources,
+            create_options(&config, &platform, flow, &flow_modules)?,
+        )?
+        .commit()?;
+        Ok(std::fs::read_to_string(output)?)
+    }
+
+    #[test]
+    fn test_build_info_banner_and_global() -> Result<()> {
+        let directory = TestDirectory::create();
+        let bundle = bundle_source(
+            &directory,
+            "build_info = {}",
+            "print(BUILD_INFO.cli_version, BUILD_INFO.source_hash)\n",
+        )?;
+
+        let (banner, code) = bundle.split_once('\n').unwrap();
+        assert!(banner.starts_with(&format!(
+            "-- Built by opacity-cli {}",
+            env!("CARGO_PKG_VERSION")
+        )));
+        // no timestamp unless asked for, so the banner doesn't change from one build to the next
+        assert!(!banner.contains(" at "));
+        let source_hash = banner
+            .split_once("(sources ")
+            .and_then(|(_, rest)| rest.strip_suffix(')'))
+            .unwrap();
+        assert_eq!(source_hash.len(), 64);
+
+        assert!(!code.contains("BUILD_INFO"));
+        assert!(code.contains(env!("CARGO_PKG_VERSION")));
+        assert!(code.contains(source_hash));
+        Ok(())
+    }
+
+    #[test]
+    fn test_disabled_feature_branches_are_removed() -> Result<()> {
+        let directory = TestDirectory::create();
+        let bundle = bundle_source(
+            &directory,
+            "features = { VERBOSE_LOGS = false }",
+            "if VERBOSE_LOGS then\n\tprint(\"verbose\")\nelse\n\tprint(\"quiet\")\nend\n",
+        )?;
+
+        assert!(bundle.contains("quiet"));
+        assert!(!bundle.contains("verbose"));
+        assert!(!bundle.contains("VERBOSE_LOGS"));
+        Ok(())
+    }
+}
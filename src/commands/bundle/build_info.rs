@@ -0,0 +1,132 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+
+use crate::commands::bundle::{display_path, sha256_hex, FlowModules};
+use crate::config::BuildInfoSettings;
+
+/// Where `.git` is for the current directory, following `gitdir:` files (worktrees, submodules)
+fn find_git_dir() -> Option<PathBuf> {
+    let current_dir = std::env::current_dir().ok()?;
+    let dot_git = current_dir
+        .ancestors()
+        .map(|directory| directory.join(".git"))
+        .find(|dot_git| dot_git.exists())?;
+
+    if dot_git.is_file() {
+        let content = std::fs::read_to_string(&dot_git).ok()?;
+        let git_dir = Path::new(content.strip_prefix("gitdir:")?.trim());
+        Some(dot_git.parent()?.join(git_dir))
+    } else {
+        Some(dot_git)
+    }
+}
+
+/// Reads the commit checked out in the local repository, without calling git
+pub fn read_git_commit() -> Option<String> {
+    let git_dir = find_git_dir()?;
+    let head = std::fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let Some(reference) = head.trim().strip_prefix("ref:").map(str::trim) else {
+        // detached HEAD
+        return Some(head.trim().to_string());
+    };
+
+    // worktrees keep their refs in the common directory
+    let common_dir = std::fs::read_to_string(git_dir.join("commondir"))
+        .map(|common_dir| git_dir.join(common_dir.trim()))
+        .unwrap_or_else(|_| git_dir.clone());
+
+    [&git_dir, &common_dir]
+        .iter()
+        .find_map(|directory| std::fs::read_to_string(directory.join(reference)).ok())
+        .map(|commit| commit.trim().to_string())
+        .or_else(|| {
+            std::fs::read_to_string(common_dir.join("packed-refs"))
+                .ok()?
+                .lines()
+                .filter_map(|line| line.split_once(' '))
+                .find(|(_, name)| *name == reference)
+                .map(|(commit, _)| commit.to_string())
+        })
+}
+
+/// Hash of every module the flow embeds, so a bundle can be traced back to its sources
+fn source_hash(flow_modules: &FlowModules) -> Result<String> {
+    let mut modules = flow_modules
+        .modules
+        .iter()
+        .map(|module| Ok((display_path(module), sha256_hex(&std::fs::read(module)?))))
+        .collect::<Result<Vec<_>>>()?;
+    modules.sort();
+
+    Ok(sha256_hex(
+        modules
+            .iter()
+            .map(|(path, hash)| format!("{}:{}\n", path, hash))
+            .collect::<String>()
+            .as_bytes(),
+    ))
+}
+
+/// `SOURCE_DATE_EPOCH` (https://reproducible-builds.org/specs/source-date-epoch/), if set
+pub fn source_date_epoch() -> Option<i64> {
+    std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<i64>().ok())
+}
+
+/// Build time, honoring `SOURCE_DATE_EPOCH`
+fn build_time() -> String {
+    source_date_epoch()
+        .and_then(|epoch| DateTime::from_timestamp(epoch, 0))
+        .unwrap_or_else(Utc::now)
+        .to_rfc3339()
+}
+
+pub struct BuildInfo {
+    pub cli_version: String,
+    pub commit: Option<String>,
+    pub source_hash: String,
+    pub built_at: Option<String>,
+}
+
+impl BuildInfo {
+    pub fn new(build_info: &BuildInfoSettings, flow_modules: &FlowModules) -> Result<Self> {
+        Ok(Self {
+            cli_version: env!("CARGO_PKG_VERSION").to_string(),
+            commit: read_git_commit(),
+            source_hash: source_hash(flow_modules)?,
+            built_at: build_info.timestamp.then(build_time),
+        })
+    }
+
+    /// Comment prepended to the bundle
+    pub fn banner(&self) -> String {
+        let mut banner = format!("-- Built by opacity-cli {}", self.cli_version);
+        if let Some(commit) = &self.commit {
+            banner.push_str(&format!(" from commit {}", commit));
+        }
+        banner.push_str(&format!(" (sources {})", self.source_hash));
+        if let Some(built_at) = &self.built_at {
+            banner.push_str(&format!(" at {}", built_at));
+        }
+        banner
+    }
+
+    /// Value of the `BUILD_INFO` global
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut value = json!({
+            "cli_version": self.cli_version,
+            "source_hash": self.source_hash,
+        });
+        if let Some(commit) = &self.commit {
+            value["commit"] = json!(commit);
+        }
+        if let Some(built_at) = &self.built_at {
+            value["built_at"] = json!(built_at);
+        }
+        value
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::commands::bundle::{display_path, sha256_hex, FlowModules};
+use crate::paths::relative_to;
+
+pub const HASHES_LOCK_FILE_NAME: &str = "hashes.lock";
+
+const HASHES_LOCK_VERSION: u32 = 2;
+
+/// `hashes.lock`, keyed by flow alias so it's the same on every machine and can be committed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HashesLock {
+    pub version: u32,
+    #[serde(default)]
+    pub flows: BTreeMap<String, FlowHashes>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlowHashes {
+    /// Path of the bundle, relative to the config directory
+    pub bundle: String,
+    /// SHA-256 of the bundle
+    pub hash: String,
+    /// SHA-256 of every module embedded in the bundle, keyed by their path relative to the
+    /// config directory (empty when migrated from the legacy format)
+    #[serde(default)]
+    pub sources: BTreeMap<String, String>,
+}
+
+impl FlowHashes {
+    pub fn new(
+        config_dir: &Path,
+        flow_modules: &FlowModules,
+        output: &Path,
+        content: &[u8],
+    ) -> Result<Self> {
+        let sources = flow_modules
+            .modules
+            .iter()
+            .map(|module| {
+                let content = std::fs::read(module).map_err(|e| {
+                    anyhow::anyhow!("Failed to read file {}: {}", module.display(), e)
+                })?;
+                Ok((
+                    display_path(&relative_to(module, config_dir)),
+                    sha256_hex(&content),
+                ))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            bundle: display_path(&relative_to(output, config_dir)),
+            hash: sha256_hex(content),
+            sources,
+        })
+    }
+}
+
+impl Default for HashesLock {
+    fn default() -> Self {
+        Self {
+            version: HASHES_LOCK_VERSION,
+            flows: BTreeMap::new(),
+        }
+    }
+}
+
+impl HashesLock {
+    /// `hashes.lock`, or `hashes.<profile>.lock` so profiles don't clobber each other
+    pub fn path(config_dir: &Path, profile: Option<&str>) -> PathBuf {
+        match profile {
+            Some(profile) => config_dir.join(format!("hashes.{}.lock", profile)),
+            None => config_dir.join(HASHES_LOCK_FILE_NAME),
+        }
+    }
+
+    /// Parses a lock file, migrating the legacy `<absolute path>:<hash>` format if needed
+    pub fn parse(content: &str, config_dir: &Path) -> Result<Self> {
+        if let Ok(lock) = toml::from_str::<HashesLock>(content) {
+            if lock.version > HASHES_LOCK_VERSION {
+                anyhow::bail!(
+                    "hashes.lock version {} is newer than supported ({}), please update the CLI",
+                    lock.version,
+                    HASHES_LOCK_VERSION
+                );
+            }
+            return Ok(lock);
+        }
+
+        let mut lock = HashesLock::default();
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let (path, hash) = line
+                .rsplit_once(':')
+                .ok_or_else(|| anyhow::anyhow!("Invalid line in hashes.lock: {}", line))?;
+            let path = Path::new(path);
+            let alias = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".bundle.luau"))
+                .ok_or_else(|| anyhow::anyhow!("Invalid bundle path in hashes.lock: {}", line))?;
+
+            lock.flows.insert(
+                alias.to_string(),
+                FlowHashes {
+                    bundle: display_path(&relative_to(path, config_dir)),
+                    hash: hash.to_string(),
+                    sources: BTreeMap::new(),
+                },
+            );
+        }
+        Ok(lock)
+    }
+
+    /// Reads the lock next to the config, if there is one
+    pub fn read(config_dir: &Path, profile: Option<&str>) -> Result<Option<Self>> {
+        let path = Self::path(config_dir, profile);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Self::parse(&std::fs::read_to_string(&path)?, config_dir)
+            .map(Some)
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))
+    }
+
+    pub fn write(&self, config_dir: &Path, profile: Option<&str>) -> Result<()> {
+        std::fs::write(
+            Self::path(config_dir, profile),
+            toml::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_legacy_format() {
+        let lock = HashesLock::parse(
+            "/home/alice/proj/out/first.bundle.luau:abcd\n/home/alice/proj/out/second.bundle.luau:ef01",
+            Path::new("/home/alice/proj"),
+        )
+        .unwrap();
+
+        assert_eq!(lock.version, HASHES_LOCK_VERSION);
+        assert_eq!(lock.flows["first"].bundle, "out/first.bundle.luau");
+        assert_eq!(lock.flows["first"].hash, "abcd");
+        assert_eq!(lock.flows["second"].hash, "ef01");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut lock = HashesLock::default();
+        lock.flows.insert(
+            "first".to_string(),
+            FlowHashes {
+                bundle: "out/first.bundle.luau".to_string(),
+                hash: "abcd".to_string(),
+                sources: BTreeMap::from([("src/first.luau".to_string(), "1234".to_string())]),
+            },
+        );
+
+        let parsed =
+            HashesLock::parse(&toml::to_string_pretty(&lock).unwrap(), Path::new("")).unwrap();
+        assert_eq!(parsed.flows, lock.flows);
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::commands::bundle::size_report::SizeReport;
+use crate::commands::bundle::{display_path, sha256_hex, FlowModules};
+use crate::commands::version::sdk_version::SdkVersionOut;
+use crate::config::{Flow, SdkVariant, SimplePlatform};
+use crate::paths::relative_to;
+
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// Everything the deployment pipeline needs to know about the bundles of an output directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    /// Version of the CLI that produced the bundles
+    pub cli_version: String,
+    /// Build profile the bundles were made with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    pub flows: Vec<ManifestFlow>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestFlow {
+    pub alias: String,
+    pub name: String,
+    pub platform: String,
+    pub description: String,
+    pub retrieves: Option<Vec<String>>,
+    /// `minSdkVersion` as declared in opacity.toml
+    pub min_sdk_version: Option<String>,
+    /// SDK range computed from the dependency graph, if there is a version_file.json
+    pub computed_sdk_version: Option<SdkVersionOut>,
+    /// Bundle file name, relative to the output directory
+    pub bundle: String,
+    pub size: u64,
+    /// 0 in manifests written before it was recorded
+    #[serde(default)]
+    pub gzip_size: u64,
+    pub sha256: String,
+    /// Hex-encoded Ed25519 signature of the bundle, when bundled with `--sign`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Feature flags the bundle was built with
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub features: BTreeMap<String, bool>,
+    /// Bundles for SDK ranges, the serving side picks the one whose range contains the
+    /// client's SDK version and falls back to `bundle`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variants: Vec<ManifestVariant>,
+    /// Every module embedded in the bundle, the flow itself first, relative to the config
+    /// directory like the sources of hashes.lock
+    pub modules: Vec<String>,
+}
+
+impl ManifestFlow {
+    pub fn new(
+        config_dir: &Path,
+        platform: &SimplePlatform,
+        flow: &Flow,
+        flow_modules: &FlowModules,
+        output: &Path,
+        content: &[u8],
+        size_report: &SizeReport,
+    ) -> Self {
+        Self {
+            alias: flow.alias.clone(),
+            name: flow.name.clone(),
+            platform: platform.name.clone(),
+            description: flow.description.clone(),
+            retrieves: flow.retrieves.clone(),
+            min_sdk_version: flow.min_sdk_version.clone(),
+            computed_sdk_version: flow_modules.sdk_version.clone(),
+            bundle: output
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            size: content.len() as u64,
+            gzip_size: size_report.gzip_size,
+            sha256: sha256_hex(content),
+            signature: None,
+            features: BTreeMap::new(),
+            variants: Vec::new(),
+            modules: flow_modules
+                .modules
+                .iter()
+                .map(|module| display_path(&relative_to(module, config_dir)))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestVariant {
+    /// Lowest SDK version of the range, inclusive
+    pub min_sdk_version: Option<u64>,
+    /// Highest SDK version of the range, inclusive
+    pub max_sdk_version: Option<u64>,
+    /// Bundle file name, relative to the output directory
+    pub bundle: String,
+    pub size: u64,
+    #[serde(default)]
+    pub gzip_size: u64,
+    pub sha256: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+impl ManifestVariant {
+    pub fn new(
+        variant: &SdkVariant,
+        output: &Path,
+        content: &[u8],
+        size_report: &SizeReport,
+    ) -> Self {
+        Self {
+            min_sdk_version: variant.min,
+            max_sdk_version: variant.max,
+            bundle: output
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            size: content.len() as u64,
+            gzip_size: size_report.gzip_size,
+            sha256: sha256_hex(content),
+            signature: None,
+        }
+    }
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Self {
+            cli_version: env!("CARGO_PKG_VERSION").to_string(),
+            profile: None,
+            flows: Vec::new(),
+        }
+    }
+
+    pub fn path(output_directory: &str) -> PathBuf {
+        PathBuf::from(output_directory).join(MANIFEST_FILE_NAME)
+    }
+
+    /// Reads the manifest of a previous `bundle` run, if there is one
+    pub fn read(output_directory: &str) -> Result<Option<Self>> {
+        let path = Self::path(output_directory);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let manifest = serde_json::from_str(&std::fs::read_to_string(&path)?)
+            .map_err(|e| anyhow::anyhow!("Failed to parse {}: {}", path.display(), e))?;
+        Ok(Some(manifest))
+    }
+
+    pub fn get_flow(&self, alias: &str) -> Option<&ManifestFlow> {
+        self.flows.iter().find(|flow| flow.alias == alias)
+    }
+
+    pub fn write(&self, output_directory: &str) -> Result<()> {
+        std::fs::write(
+            Self::path(output_directory),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_manifest_without_gzip_size() {
+        let manifest: Manifest = serde_json::from_str(
+            r#"{
+                "cliVersion": "0.1.0",
+                "flows": [{
+                    "alias": "example",
+                    "name": "Example",
+                    "platform": "example",
+                    "description": "Example flow",
+                    "retrieves": null,
+                    "minSdkVersion": null,
+                    "computedSdkVersion": null,
+                    "bundle": "example.bundle.luau",
+                    "size": 10,
+                    "sha256": "abc",
+                    "variants": [{
+                        "minSdkVersion": 26,
+                        "maxSdkVersion": null,
+                        "bundle": "example@26-.bundle.luau",
+                        "size": 8,
+                        "sha256": "def"
+                    }],
+                    "modules": ["example.luau"]
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        let flow = manifest.get_flow("example").unwrap();
+        assert_eq!(flow.gzip_size, 0);
+        assert_eq!(flow.variants[0].gzip_size, 0);
+    }
+}
@@ -0,0 +1,435 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use darklua_core::nodes::{
+    self, Arguments, Expression, FieldExpression, FunctionCall, Identifier, Prefix,
+    StringExpression, TupleArguments,
+};
+use darklua_core::process::{DefaultVisitor, NodeProcessor, NodeVisitor, Scope, ScopeVisitor};
+use darklua_core::rules::{
+    Context, FlawlessRule, RuleConfiguration, RuleConfigurationError, RuleProperties,
+};
+use darklua_core::ScopedHashMap;
+
+use crate::commands::bundle::sha256_hex;
+use crate::config::ObfuscationSettings;
+
+const BUNDLE_MODULES_IDENTIFIER: &str = "__BUNDLE_MODULES";
+const BUNDLE_CACHE_FIELD: &str = "cache";
+
+/// Reverses `encode_string` at runtime, `DECODER` is replaced with the decoder's name
+const STRING_DECODER_SOURCE: &str = r#"
+local function DECODER(value, key)
+    return (string.gsub(value, "%x%x", function(byte)
+        return string.char(bit32.bxor(tonumber(byte, 16), key))
+    end))
+end
+"#;
+
+/// What the obfuscation pass did to a bundle, so its source map can still be generated
+#[derive(Debug, Clone, Default)]
+pub struct ObfuscationMap {
+    /// Original name of every renamed local, by its new name
+    pub names: HashMap<String, String>,
+    /// Name of the function decoding the encoded strings, if they were encoded
+    pub string_decoder: Option<String>,
+}
+
+/// Filled in by the obfuscation rule while darklua processes the bundle
+pub type SharedObfuscationMap = Arc<Mutex<ObfuscationMap>>;
+
+/// `_a`, `_b`, ..., `_Z`, `_ba`, ...: never a keyword
+fn obfuscated_name(mut index: usize) -> String {
+    const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    let mut name = Vec::new();
+    loop {
+        name.push(ALPHABET[index % ALPHABET.len()]);
+        index /= ALPHABET.len();
+        if index == 0 {
+            break;
+        }
+    }
+    name.push(b'_');
+    name.reverse();
+    String::from_utf8(name).unwrap()
+}
+
+/// Hands out names that are unique across the whole bundle and clash with none of its
+/// original names, so every new name translates back to exactly one original name
+struct NameGenerator {
+    reserved: HashSet<String>,
+    next: usize,
+}
+
+impl NameGenerator {
+    fn next_name(&mut self) -> String {
+        loop {
+            let name = obfuscated_name(self.next);
+            self.next += 1;
+            if self.reserved.insert(name.clone()) {
+                return name;
+            }
+        }
+    }
+}
+
+/// Key of the `index`th encoded string, never 0 so no byte is left as is
+fn string_key(seed: u64, index: usize) -> u8 {
+    let mut value = seed.wrapping_add((index as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    value ^= value >> 31;
+    value = value.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    value ^= value >> 29;
+    (value % 255) as u8 + 1
+}
+
+/// Hex of the string's bytes XORed with `key`
+fn encode_string(value: &str, key: u8) -> String {
+    value
+        .bytes()
+        .map(|byte| format!("{:02x}", byte ^ key))
+        .collect()
+}
+
+/// Collects every name the bundle uses, globals included
+#[derive(Default)]
+struct NameCollector {
+    names: HashSet<String>,
+}
+
+impl NodeProcessor for NameCollector {
+    fn process_variable_expression(&mut self, identifier: &mut nodes::Identifier) {
+        self.names.insert(identifier.get_name().to_string());
+    }
+
+    fn process_function_statement(&mut self, function: &mut nodes::FunctionStatement) {
+        self.names
+            .insert(function.get_name().get_name().get_name().to_string());
+    }
+}
+
+impl Scope for NameCollector {
+    fn push(&mut self) {}
+    fn pop(&mut self) {}
+    fn insert(&mut self, identifier: &mut String) {
+        self.names.insert(identifier.clone());
+    }
+    fn insert_local(&mut self, identifier: &mut String, _: Option<&mut nodes::Expression>) {
+        self.names.insert(identifier.clone());
+    }
+    fn insert_local_function(&mut self, function: &mut nodes::LocalFunctionStatement) {
+        self.names.insert(function.get_name().to_string());
+    }
+    fn insert_self(&mut self) {}
+}
+
+/// Renames the keys of `__BUNDLE_MODULES` (and of its cache), which follow the order the
+/// modules were bundled in
+struct ModuleKeyRenamer<'a> {
+    names: &'a mut NameGenerator,
+    keys: HashMap<String, String>,
+}
+
+impl ModuleKeyRenamer<'_> {
+    fn rename(&mut self, key: &str) -> String {
+        // already renamed, the generated names clash with no original key
+        if self.keys.values().any(|renamed| renamed == key) {
+            return key.to_string();
+        }
+        if let Some(renamed) = self.keys.get(key) {
+            return renamed.clone();
+        }
+        let renamed = self.names.next_name();
+        self.keys.insert(key.to_string(), renamed.clone());
+        renamed
+    }
+}
+
+fn is_bundle_modules(prefix: &Prefix) -> bool {
+    match prefix {
+        Prefix::Identifier(identifier) => identifier.get_name() == BUNDLE_MODULES_IDENTIFIER,
+        _ => false,
+    }
+}
+
+impl NodeProcessor for ModuleKeyRenamer<'_> {
+    fn process_field_expression(&mut self, field: &mut FieldExpression) {
+        let is_module_key = match field.get_prefix() {
+            Prefix::Field(cache) => {
+                cache.get_field().get_name() == BUNDLE_CACHE_FIELD
+                    && is_bundle_modules(cache.get_prefix())
+            }
+            prefix => {
+                is_bundle_modules(prefix) && field.get_field().get_name() != BUNDLE_CACHE_FIELD
+            }
+        };
+        if is_module_key {
+            let key = self.rename(field.get_field().get_name());
+            *field = FieldExpression::new(field.get_prefix().clone(), Identifier::new(key));
+        }
+    }
+
+    fn process_function_statement(&mut self, function: &mut nodes::FunctionStatement) {
+        let name = function.get_name();
+        if name.get_name().get_name() != BUNDLE_MODULES_IDENTIFIER
+            || name.get_field_names().len() != 1
+        {
+            return;
+        }
+
+        let key = self.rename(name.get_field_names()[0].get_name());
+        let renamed = nodes::FunctionName::new(
+            name.get_name().clone(),
+            vec![Identifier::new(key)],
+            name.get_method().cloned(),
+        );
+        *function.mutate_function_name() = renamed;
+    }
+}
+
+/// Gives every local, parameter and local function a generated name
+struct LocalRenamer<'a> {
+    names: &'a mut NameGenerator,
+    rename_locals: bool,
+    module_names: bool,
+    locals: ScopedHashMap<String, String>,
+    renamed: HashMap<String, String>,
+}
+
+impl LocalRenamer<'_> {
+    fn declare(&mut self, identifier: &mut String) {
+        let should_rename = self.rename_locals
+            || (self.module_names && identifier.as_str() == BUNDLE_MODULES_IDENTIFIER);
+        // locals that keep their name still shadow the renamed ones
+        let name = if should_rename {
+            let name = self.names.next_name();
+            self.renamed.insert(name.clone(), identifier.clone());
+            name
+        } else {
+            identifier.clone()
+        };
+        self.locals.insert(identifier.clone(), name.clone());
+        *identifier = name;
+    }
+
+    fn renamed(&self, name: &str) -> Option<String> {
+        self.locals
+            .get(&name.to_string())
+            .filter(|renamed| renamed.as_str() != name)
+            .cloned()
+    }
+}
+
+impl NodeProcessor for LocalRenamer<'_> {
+    fn process_variable_expression(&mut self, identifier: &mut nodes::Identifier) {
+        if let Some(renamed) = self.renamed(identifier.get_name()) {
+            identifier.set_name(renamed);
+        }
+    }
+
+    fn process_function_statement(&mut self, function: &mut nodes::FunctionStatement) {
+        let name = function.get_name().get_name().get_name().clone();
+        if let Some(renamed) = self.renamed(&name) {
+            function
+                .mutate_function_name()
+                .mutate_identifier()
+                .set_name(renamed);
+        }
+    }
+}
+
+impl Scope for LocalRenamer<'_> {
+    fn push(&mut self) {
+        self.locals.push();
+    }
+    fn pop(&mut self) {
+        self.locals.pop();
+    }
+    fn insert(&mut self, identifier: &mut String) {
+        self.declare(identifier);
+    }
+    fn insert_local(&mut self, identifier: &mut String, _: Option<&mut nodes::Expression>) {
+        self.declare(identifier);
+    }
+    fn insert_local_function(&mut self, function: &mut nodes::LocalFunctionStatement) {
+        self.declare(function.mutate_identifier().mutate_name());
+    }
+    fn insert_self(&mut self) {}
+}
+
+/// Replaces string literals with a call to the decoder
+struct StringEncoder<'a> {
+    decoder: &'a str,
+    seed: u64,
+    count: usize,
+    /// The encoded strings are visited right after being created, they must stay as they are
+    encoded: HashSet<String>,
+}
+
+impl StringEncoder<'_> {
+    fn encode(&mut self, string: &StringExpression) -> Option<FunctionCall> {
+        let value = string.get_string_value()?;
+        if value.is_empty() || self.encoded.contains(value) {
+            return None;
+        }
+
+        let key = string_key(self.seed, self.count);
+        self.count += 1;
+        let encoded = encode_string(value, key);
+        self.encoded.insert(encoded.clone());
+
+        Some(
+            FunctionCall::from_name(self.decoder)
+                .with_argument(StringExpression::from_value(encoded))
+                .with_argument(Expression::from(f64::from(key))),
+        )
+    }
+}
+
+impl NodeProcessor for StringEncoder<'_> {
+    fn process_expression(&mut self, expression: &mut Expression) {
+        if let Expression::String(string) = expression {
+            if let Some(call) = self.encode(string) {
+                *expression = call.into();
+            }
+        }
+    }
+
+    fn process_function_call(&mut self, call: &mut FunctionCall) {
+        // `f "value"` has to become `f(decode(...))`
+        if let Arguments::String(string) = call.get_arguments() {
+            if let Some(encoded) = self.encode(string) {
+                *call.mutate_arguments() = TupleArguments::default().with_argument(encoded).into();
+            }
+        }
+    }
+}
+
+/// darklua rule obfuscating a whole bundle: renames locals and the bundled modules, and
+/// encodes string literals. It runs last, once the globals are injected
+#[derive(Debug)]
+pub struct ObfuscateBundle {
+    settings: ObfuscationSettings,
+    /// Strings are encoded the same way every time the same bundle is built
+    seed: u64,
+    map: SharedObfuscationMap,
+}
+
+impl ObfuscateBundle {
+    pub fn new(settings: &ObfuscationSettings, bundle_name: &str) -> Self {
+        let seed = u64::from_str_radix(&sha256_hex(bundle_name.as_bytes())[..16], 16).unwrap();
+        Self {
+            settings: settings.clone(),
+            seed,
+            map: SharedObfuscationMap::default(),
+        }
+    }
+
+    pub fn map(&self) -> SharedObfuscationMap {
+        self.map.clone()
+    }
+}
+
+impl FlawlessRule for ObfuscateBundle {
+    fn flawless_process(&self, block: &mut nodes::Block, _: &Context) {
+        let mut collector = NameCollector::default();
+        ScopeVisitor::visit_block(block, &mut collector);
+        let mut names = NameGenerator {
+            reserved: collector.names,
+            next: 0,
+        };
+        let mut map = ObfuscationMap::default();
+
+        if self.settings.module_names {
+            let mut renamer = ModuleKeyRenamer {
+                names: &mut names,
+                keys: HashMap::new(),
+            };
+            DefaultVisitor::visit_block(block, &mut renamer);
+        }
+
+        if self.settings.rename_locals || self.settings.module_names {
+            let mut renamer = LocalRenamer {
+                names: &mut names,
+                rename_locals: self.settings.rename_locals,
+                module_names: self.settings.module_names,
+                locals: ScopedHashMap::default(),
+                renamed: HashMap::new(),
+            };
+            ScopeVisitor::visit_block(block, &mut renamer);
+            map.names = renamer.renamed;
+        }
+
+        if self.settings.encode_strings {
+            let decoder = names.next_name();
+            let mut encoder = StringEncoder {
+                decoder: &decoder,
+                seed: self.seed,
+                count: 0,
+                encoded: HashSet::new(),
+            };
+            DefaultVisitor::visit_block(block, &mut encoder);
+
+            if encoder.count > 0 {
+                let decoder_block = darklua_core::Parser::default()
+                    .parse(&STRING_DECODER_SOURCE.replace("DECODER", &decoder))
+                    .expect("the string decoder is valid Luau");
+                let statements = decoder_block.iter_statements().cloned().collect::<Vec<_>>();
+                for statement in statements.into_iter().rev() {
+                    block.insert_statement(0, statement);
+                }
+                map.string_decoder = Some(decoder);
+            }
+        }
+
+        *self.map.lock().unwrap() = map;
+    }
+}
+
+impl RuleConfiguration for ObfuscateBundle {
+    fn configure(&mut self, _: RuleProperties) -> Result<(), RuleConfigurationError> {
+        Ok(())
+    }
+
+    fn get_name(&self) -> &'static str {
+        "obfuscate_bundle"
+    }
+
+    fn serialize_to_properties(&self) -> RuleProperties {
+        RuleProperties::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_obfuscated_names_are_unique() {
+        let names = (0..3000).map(obfuscated_name).collect::<HashSet<_>>();
+        assert_eq!(names.len(), 3000);
+        assert_eq!(obfuscated_name(0), "_a");
+        assert_eq!(obfuscated_name(51), "_Z");
+        assert_eq!(obfuscated_name(52), "_ba");
+
+        let mut generator = NameGenerator {
+            reserved: HashSet::from(["_a".to_string()]),
+            next: 0,
+        };
+        assert_eq!(generator.next_name(), "_b");
+    }
+
+    #[test]
+    fn test_encode_string() {
+        let key = string_key(42, 0);
+        assert_ne!(key, 0);
+        assert_eq!(string_key(42, 0), key);
+
+        let encoded = encode_string("https://example.com", key);
+        assert_eq!(encoded.len(), "https://example.com".len() * 2);
+        let decoded = (0..encoded.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&encoded[index..index + 2], 16).unwrap() ^ key)
+            .collect::<Vec<_>>();
+        assert_eq!(decoded, b"https://example.com");
+    }
+}
@@ -0,0 +1,272 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use darklua_core::Resources;
+use rand_core::RngCore;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::commands::bundle::{
+    build_flow, bundle_aliases, collect_flow_modules, create_options_in, variant_alias,
+    variant_sdk_version_function, FlowModules, VariantTarget,
+};
+use crate::commands::version::version_visitor::VersionFile;
+use crate::config::{Config, Flow, SimplePlatform};
+
+/// How many bytes are shown on each side of the first difference
+const CONTEXT_LENGTH: usize = 32;
+
+/// Prefix of the directories the builds go to, inside the output directory so another project
+/// verifying at the same time has its own. `clean` removes any left behind
+pub const REPRODUCIBLE_DIRECTORY_PREFIX: &str = ".reproducible-";
+
+/// Where two builds of the same bundle first differ, and a guess of why
+#[derive(Debug, PartialEq, Eq)]
+pub struct Difference {
+    pub offset: usize,
+    /// 1-based line and column of the difference in the first build
+    pub line: usize,
+    pub column: usize,
+    pub cause: &'static str,
+}
+
+fn shuffle<T>(items: &mut [T]) {
+    let mut rng = rand_core::OsRng;
+    for i in (1..items.len()).rev() {
+        let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
+
+fn contains(haystack: &[u8], needle: &str) -> bool {
+    !needle.is_empty()
+        && haystack
+            .windows(needle.len())
+            .any(|window| window == needle.as_bytes())
+}
+
+/// Compares two builds of the same bundle, `build_directories` being the output directory of each
+pub fn find_difference(
+    first: &[u8],
+    second: &[u8],
+    build_directories: [&str; 2],
+) -> Option<Difference> {
+    let offset = first
+        .iter()
+        .zip(second.iter())
+        .position(|(lhs, rhs)| lhs != rhs)
+        .or_else(|| (first.len() != second.len()).then(|| first.len().min(second.len())))?;
+
+    let cause = if contains(first, build_directories[0]) || contains(second, build_directories[1]) {
+        "the absolute path of the output directory is embedded in the bundle"
+    } else if first.len() == second.len() && {
+        let (mut sorted_first, mut sorted_second) = (first.to_vec(), second.to_vec());
+        sorted_first.sort_unstable();
+        sorted_second.sort_unstable();
+        sorted_first == sorted_second
+    } {
+        "same content in a different order, e.g. the module ordering in __BUNDLE_MODULES"
+    } else if first.get(offset).is_some_and(u8::is_ascii_digit)
+        && second.get(offset).is_some_and(u8::is_ascii_digit)
+    {
+        "differing digits, e.g. a timestamp or a counter"
+    } else {
+        "unknown"
+    };
+
+    let before = &first[..offset];
+    let line = before.iter().filter(|byte| **byte == b'\n').count() + 1;
+    let column = offset
+        - before
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map(|newline| newline + 1)
+            .unwrap_or(0)
+        + 1;
+
+    Some(Difference {
+        offset,
+        line,
+        column,
+        cause,
+    })
+}
+
+fn context(content: &[u8], offset: usize) -> String {
+    let start = offset.saturating_sub(CONTEXT_LENGTH);
+    let end = (offset + CONTEXT_LENGTH).min(content.len());
+    format!(
+        "{:?}",
+        String::from_utf8_lossy(&content[start.min(end)..end])
+    )
+}
+
+fn build_all(
+    config: &Config,
+    resources: &Resources,
+    version_file: Option<&VersionFile>,
+    flows: &[(SimplePlatform, Flow, FlowModules)],
+    output_directory: &Path,
+) -> Result<()> {
+    let mut order: Vec<usize> = (0..flows.len()).collect();
+    shuffle(&mut order);
+
+    for index in order {
+        let (platform, flow, flow_modules) = &flows[index];
+        let bundle_options =
+            create_options_in(config, platform, flow, flow_modules, output_directory, None)?;
+        build_flow(resources, bundle_options)
+            .map_err(|e| anyhow::anyhow!("Failed to bundle {}: {}", flow.alias, e))?;
+
+        for variant in flow.variants.iter().flatten() {
+            let target = VariantTarget {
+                variant,
+                sdk_version_function: variant_sdk_version_function(flow, version_file)?,
+            };
+            let bundle_options = create_options_in(
+                config,
+                platform,
+                flow,
+                flow_modules,
+                output_directory,
+                Some(&target),
+            )?;
+            build_flow(resources, bundle_options).map_err(|e| {
+                anyhow::anyhow!(
+                    "Failed to bundle {}: {}",
+                    variant_alias(&flow.alias, variant),
+                    e
+                )
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+fn compare_builds(
+    flows: &[(SimplePlatform, Flow, FlowModules)],
+    build_directories: &[PathBuf; 2],
+) -> Result<()> {
+    let mut nondeterministic = 0;
+
+    for (_, flow, _) in flows {
+        for alias in bundle_aliases(flow) {
+            let file_name = format!("{}.bundle.luau", alias);
+            let first = std::fs::read(build_directories[0].join(&file_name))?;
+            let second = std::fs::read(build_directories[1].join(&file_name))?;
+
+            let Some(difference) = find_difference(
+                &first,
+                &second,
+                [
+                    &build_directories[0].to_string_lossy(),
+                    &build_directories[1].to_string_lossy(),
+                ],
+            ) else {
+                println!("Reproducible: {} ({})", flow.name, alias);
+                continue;
+            };
+
+            nondeterministic += 1;
+            println!(
+                "Not reproducible: {} ({}), first difference at byte {} (line {}, column {})",
+                flow.name, alias, difference.offset, difference.line, difference.column
+            );
+            println!("  likely cause: {}", difference.cause);
+            println!("  first build:  {}", context(&first, difference.offset));
+            println!("  second build: {}", context(&second, difference.offset));
+        }
+    }
+
+    if nondeterministic > 0 {
+        anyhow::bail!("{} bundle(s) are not reproducible", nondeterministic);
+    }
+
+    Ok(())
+}
+
+/// Bundles every flow and its SDK variants twice, each time in a fresh directory and in a
+/// shuffled order, and reports any bundle that isn't identical byte for byte
+pub fn verify_reproducible(
+    config: &Config,
+    resources: &Resources,
+    version_file: Option<&VersionFile>,
+) -> Result<()> {
+    let flows = config
+        .platforms
+        .iter()
+        .flat_map(|platform| platform.flows.iter().map(move |flow| (platform, flow)))
+        .map(|(platform, flow)| {
+            let flow_modules = collect_flow_modules(resources, &config.settings, flow, None)?;
+            Ok((SimplePlatform::from(platform), flow.clone(), flow_modules))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let build_directories = [(); 2].map(|_| {
+        Path::new(&config.settings.output_directory).join(format!(
+            "{}{}",
+            REPRODUCIBLE_DIRECTORY_PREFIX,
+            Uuid::new_v4()
+        ))
+    });
+
+    let result = build_directories
+        .iter()
+        .try_for_each(|build_directory| {
+            build_all(config, resources, version_file, &flows, build_directory)
+        })
+        .and_then(|_| compare_builds(&flows, &build_directories));
+
+    for build_directory in &build_directories {
+        let _ = std::fs::remove_dir_all(build_directory);
+    }
+
+    result?;
+    info!(
+        "All {} bundles are reproducible",
+        flows
+            .iter()
+            .map(|(_, flow, _)| bundle_aliases(flow).len())
+            .sum::<usize>()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_difference() {
+        assert_eq!(find_difference(b"same", b"same", ["/a", "/b"]), None);
+
+        let difference =
+            find_difference(b"local a\nlocal b=1", b"local a\nlocal b=2", ["/a", "/b"]);
+        assert_eq!(
+            difference,
+            Some(Difference {
+                offset: 16,
+                line: 2,
+                column: 9,
+                cause: "differing digits, e.g. a timestamp or a counter",
+            })
+        );
+
+        let difference = find_difference(
+            b"__BUNDLE_MODULES.a __BUNDLE_MODULES.b",
+            b"__BUNDLE_MODULES.b __BUNDLE_MODULES.a",
+            ["/a", "/b"],
+        )
+        .unwrap();
+        assert_eq!(difference.offset, 17);
+        assert!(difference.cause.contains("module ordering"));
+
+        let difference =
+            find_difference(b"x='/tmp/a/x'", b"x='/tmp/b/x'", ["/tmp/a", "/tmp/b"]).unwrap();
+        assert!(difference.cause.contains("absolute path"));
+
+        let difference = find_difference(b"short", b"shorter", ["/a", "/b"]).unwrap();
+        assert_eq!(difference.offset, 5);
+    }
+}
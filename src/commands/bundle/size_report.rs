@@ -0,0 +1,122 @@
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Result;
+use flate2::{write::GzEncoder, Compression};
+
+use crate::commands::bundle::source_map::SourceMap;
+use crate::config::{Config, Flow, SimplePlatform};
+use crate::diagnostics::Diagnostic;
+
+/// How many modules are listed under each flow in the size report
+const LARGEST_MODULES_COUNT: usize = 5;
+
+pub fn gzip_size(content: &[u8]) -> Result<u64> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(content)?;
+    Ok(encoder.finish()?.len() as u64)
+}
+
+pub fn format_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    }
+}
+
+pub struct SizeReport {
+    pub size: u64,
+    pub gzip_size: u64,
+    /// Bytes of the bundle each module accounts for, largest first
+    pub modules: Vec<(Option<String>, u64)>,
+}
+
+impl SizeReport {
+    pub fn new(generated: &str, source_map: &SourceMap) -> Result<Self> {
+        Ok(Self {
+            size: generated.len() as u64,
+            gzip_size: gzip_size(generated.as_bytes())?,
+            modules: source_map.generated_bytes_per_source(generated)?,
+        })
+    }
+
+    pub fn print(&self) {
+        println!(
+            "  Size: {} ({} gzipped)",
+            format_size(self.size),
+            format_size(self.gzip_size)
+        );
+        for (module, size) in self.modules.iter().take(LARGEST_MODULES_COUNT) {
+            println!(
+                "    {:>10}  {}",
+                format_size(*size),
+                module.as_deref().unwrap_or("(bundle runtime)")
+            );
+        }
+    }
+}
+
+/// Size limits of a flow, the flow's own settings win over the platform's, which win over `[settings]`
+pub struct SizeBudget {
+    pub max_size: Option<u64>,
+    pub max_growth: Option<f64>,
+}
+
+impl SizeBudget {
+    pub fn new(config: &Config, platform: &SimplePlatform, flow: &Flow) -> Self {
+        Self {
+            max_size: flow
+                .max_bundle_size
+                .or(platform.max_bundle_size)
+                .or(config.settings.max_bundle_size),
+            max_growth: flow
+                .max_bundle_growth
+                .or(platform.max_bundle_growth)
+                .or(config.settings.max_bundle_growth),
+        }
+    }
+
+    /// Warns if the bundle `alias` of the flow grew too much since the previous manifest,
+    /// and returns an error message if it is over its maximum size
+    pub fn check(
+        &self,
+        flow: &Flow,
+        alias: &str,
+        report: &SizeReport,
+        previous_size: Option<u64>,
+    ) -> Option<String> {
+        if let (Some(max_growth), Some(previous_size)) = (self.max_growth, previous_size) {
+            if previous_size > 0 {
+                let growth = (report.size as f64 - previous_size as f64) / previous_size as f64;
+                if growth * 100.0 > max_growth {
+                    Diagnostic::warning(
+                        "bundle-size",
+                        format!(
+                            "{} grew by {:.1}% ({} -> {}), more than the allowed {}%",
+                            alias,
+                            growth * 100.0,
+                            format_size(previous_size),
+                            format_size(report.size),
+                            max_growth
+                        ),
+                    )
+                    .with_file(Path::new(&flow.path))
+                    .emit();
+                }
+            }
+        }
+
+        match self.max_size {
+            Some(max_size) if report.size > max_size => Some(format!(
+                "{} is {} ({} bytes), over its budget of {} ({} bytes)",
+                alias,
+                format_size(report.size),
+                report.size,
+                format_size(max_size),
+                max_size
+            )),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,642 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::commands::bundle::obfuscate::ObfuscationMap;
+
+/// How many consecutive tokens have to match for the generated code to be re-synchronized
+/// with a source file (after a require was rewritten, a global was injected, etc.)
+const SYNC_WINDOW: usize = 3;
+
+/// How many source tokens can be skipped (e.g. removed by a rule) before we try to re-synchronize
+const SKIP_LOOKAHEAD: usize = 4;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    /// normalized text of the token; strings and numbers are collapsed as the generator
+    /// is free to re-format them
+    text: String,
+    line: u32,
+    column: u32,
+}
+
+/// A (very) small Luau tokenizer, it only needs to be good enough to line up
+/// the generated code with the original code, so comments and whitespace are dropped
+fn tokenize(source: &str) -> Vec<Token> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let (mut i, mut line, mut column) = (0, 0u32, 0u32);
+
+    // advances `i` up to `end`, keeping track of the line and column
+    let advance = |i: &mut usize, end: usize, line: &mut u32, column: &mut u32| {
+        while *i < end && *i < chars.len() {
+            if chars[*i] == '\n' {
+                *line += 1;
+                *column = 0;
+            } else {
+                *column += 1;
+            }
+            *i += 1;
+        }
+    };
+
+    // returns the end of a long bracket (`[==[ ... ]==]`) starting at `start`, if there is one
+    let long_bracket_end = |start: usize| -> Option<usize> {
+        let mut level = 0;
+        let mut j = start + 1;
+        while chars.get(j) == Some(&'=') {
+            level += 1;
+            j += 1;
+        }
+        if chars.get(j) != Some(&'[') {
+            return None;
+        }
+        let mut k = j + 1;
+        while k < chars.len() {
+            if chars[k] == ']' {
+                let mut equals = 0;
+                while chars.get(k + 1 + equals) == Some(&'=') {
+                    equals += 1;
+                }
+                if equals == level && chars.get(k + 1 + equals) == Some(&']') {
+                    return Some(k + 2 + equals);
+                }
+            }
+            k += 1;
+        }
+        Some(chars.len())
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        if c.is_whitespace() {
+            let end = i + 1;
+            advance(&mut i, end, &mut line, &mut column);
+            continue;
+        }
+
+        if c == '-' && next == Some('-') {
+            let end = match chars.get(i + 2) {
+                Some('[') => long_bracket_end(i + 2),
+                _ => None,
+            }
+            .unwrap_or_else(|| {
+                (i..chars.len())
+                    .find(|j| chars[*j] == '\n')
+                    .unwrap_or(chars.len())
+            });
+            advance(&mut i, end, &mut line, &mut column);
+            continue;
+        }
+
+        let (start_line, start_column) = (line, column);
+        let (end, text) = if c == '[' && matches!(next, Some('[') | Some('=')) {
+            match long_bracket_end(i) {
+                Some(end) => (end, "<string>".to_string()),
+                None => (i + 1, c.to_string()),
+            }
+        } else if c == '"' || c == '\'' || c == '`' {
+            let mut j = i + 1;
+            let mut depth = 0;
+            while j < chars.len() {
+                match chars[j] {
+                    '\\' => j += 1,
+                    '{' if c == '`' => depth += 1,
+                    '}' if c == '`' && depth > 0 => depth -= 1,
+                    '\n' if c != '`' => break,
+                    quote if quote == c && depth == 0 => break,
+                    _ => {}
+                }
+                j += 1;
+            }
+            ((j + 1).min(chars.len()), "<string>".to_string())
+        } else if c.is_ascii_digit() || (c == '.' && next.is_some_and(|n| n.is_ascii_digit())) {
+            let mut j = i;
+            while j < chars.len() {
+                let d = chars[j];
+                let is_exponent_sign = (d == '+' || d == '-')
+                    && matches!(chars[j - 1], 'e' | 'E' | 'p' | 'P')
+                    && !(chars[i] == '0' && matches!(chars.get(i + 1), Some('x') | Some('X')));
+                if d.is_ascii_alphanumeric() || d == '_' || d == '.' || is_exponent_sign {
+                    j += 1;
+                } else {
+                    break;
+                }
+            }
+            (j, "<number>".to_string())
+        } else if c.is_alphabetic() || c == '_' {
+            let mut j = i;
+            while j < chars.len() && (chars[j].is_alphanumeric() || chars[j] == '_') {
+                j += 1;
+            }
+            (j, chars[i..j].iter().collect())
+        } else {
+            let symbol = [
+                "...", "..=", "//=", "==", "~=", "<=", ">=", "..", "::", "->", "+=", "-=", "*=",
+                "/=", "%=", "^=", "//",
+            ]
+            .iter()
+            .find(|symbol| {
+                symbol
+                    .chars()
+                    .enumerate()
+                    .all(|(offset, s)| chars.get(i + offset) == Some(&s))
+            })
+            .map(|symbol| symbol.to_string())
+            .unwrap_or_else(|| c.to_string());
+            (i + symbol.chars().count(), symbol)
+        };
+
+        tokens.push(Token {
+            text,
+            line: start_line,
+            column: start_column,
+        });
+        advance(&mut i, end, &mut line, &mut column);
+    }
+
+    tokens
+}
+
+/// Translates the tokens of an obfuscated bundle back to what its sources read: renamed locals
+/// get their name back, and each call to the string decoder becomes the string it decodes
+fn deobfuscate(tokens: Vec<Token>, obfuscation: &ObfuscationMap) -> Vec<Token> {
+    const DECODER_CALL: [&str; 5] = ["(", "<string>", ",", "<number>", ")"];
+    let mut deobfuscated = Vec::with_capacity(tokens.len());
+    let mut index = 0;
+
+    while index < tokens.len() {
+        let token = &tokens[index];
+        let is_decoder_call = obfuscation.string_decoder.as_deref() == Some(token.text.as_str())
+            && tokens
+                .get(index + 1..index + 1 + DECODER_CALL.len())
+                .is_some_and(|call| {
+                    call.iter()
+                        .map(|token| token.text.as_str())
+                        .eq(DECODER_CALL)
+                });
+
+        let text = if is_decoder_call {
+            index += DECODER_CALL.len();
+            "<string>".to_string()
+        } else {
+            obfuscation
+                .names
+                .get(&token.text)
+                .unwrap_or(&token.text)
+                .clone()
+        };
+        deobfuscated.push(Token {
+            text,
+            ..token.clone()
+        });
+        index += 1;
+    }
+
+    deobfuscated
+}
+
+fn vlq_encode(value: i64, out: &mut String) {
+    let mut vlq = if value < 0 {
+        ((-value) << 1) | 1
+    } else {
+        value << 1
+    };
+    loop {
+        let mut digit = vlq & 0b11111;
+        vlq >>= 5;
+        if vlq > 0 {
+            digit |= 0b100000;
+        }
+        out.push(BASE64_ALPHABET[digit as usize] as char);
+        if vlq == 0 {
+            break;
+        }
+    }
+}
+
+fn vlq_decode(segment: &str) -> anyhow::Result<Vec<i64>> {
+    let mut values = Vec::new();
+    let (mut value, mut shift) = (0i64, 0);
+    for c in segment.bytes() {
+        let digit = BASE64_ALPHABET
+            .iter()
+            .position(|b| *b == c)
+            .ok_or_else(|| anyhow::anyhow!("Invalid base64 character in source map: {}", c))?
+            as i64;
+        value |= (digit & 0b11111) << shift;
+        if digit & 0b100000 != 0 {
+            shift += 5;
+            continue;
+        }
+        values.push(if value & 1 == 1 {
+            -(value >> 1)
+        } else {
+            value >> 1
+        });
+        value = 0;
+        shift = 0;
+    }
+    Ok(values)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Segment {
+    generated_column: u32,
+    source: u32,
+    line: u32,
+    column: u32,
+}
+
+/// A location inside one of the original source files (1-based line and column)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OriginalLocation {
+    pub source: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// A [source map (revision 3)](https://sourcemaps.info/spec.html) for a bundle
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceMap {
+    pub version: u32,
+    pub file: String,
+    pub sources: Vec<String>,
+    #[serde(default)]
+    pub names: Vec<String>,
+    pub mappings: String,
+}
+
+impl SourceMap {
+    /// Lines up the tokens of the generated bundle with the tokens of its sources
+    ///
+    /// `sources` is a list of (path, content) of every module embedded in the bundle, and
+    /// `obfuscation` what the obfuscation pass did to it, if it ran
+    pub fn generate(
+        file: &str,
+        generated: &str,
+        sources: &[(String, String)],
+        obfuscation: Option<&ObfuscationMap>,
+    ) -> Self {
+        let source_tokens: Vec<Vec<Token>> = sources
+            .iter()
+            .map(|(_, content)| tokenize(content))
+            .collect();
+
+        let mut index: HashMap<Vec<&str>, Vec<(usize, usize)>> = HashMap::new();
+        for (source, tokens) in source_tokens.iter().enumerate() {
+            for start in 0..tokens.len().saturating_sub(SYNC_WINDOW - 1) {
+                let key = tokens[start..start + SYNC_WINDOW]
+                    .iter()
+                    .map(|token| token.text.as_str())
+                    .collect();
+                index.entry(key).or_default().push((source, start));
+            }
+        }
+
+        let generated_tokens = match obfuscation {
+            Some(obfuscation) => deobfuscate(tokenize(generated), obfuscation),
+            None => tokenize(generated),
+        };
+        let mut lines: Vec<Vec<Segment>> = Vec::new();
+        let mut cursor: Option<(usize, usize)> = None;
+        let mut previous: Option<(u32, usize, u32)> = None;
+
+        for (position, token) in generated_tokens.iter().enumerate() {
+            let continued = cursor.and_then(|(source, next)| {
+                (0..=SKIP_LOOKAHEAD)
+                    .map(|skip| next + skip)
+                    .find(|candidate| {
+                        source_tokens[source]
+                            .get(*candidate)
+                            .is_some_and(|source_token| source_token.text == token.text)
+                    })
+                    .map(|candidate| (source, candidate))
+            });
+
+            let matched = continued.or_else(|| {
+                let key: Vec<&str> = generated_tokens
+                    .get(position..position + SYNC_WINDOW)?
+                    .iter()
+                    .map(|token| token.text.as_str())
+                    .collect();
+                let candidates = index.get(&key)?;
+                // prefer staying in the same file, right after where we were
+                candidates
+                    .iter()
+                    .filter(|(source, start)| {
+                        cursor.is_some_and(|(current, next)| current == *source && *start >= next)
+                    })
+                    .min_by_key(|(_, start)| *start)
+                    .or_else(|| candidates.first())
+                    .copied()
+            });
+
+            let Some((source, source_position)) = matched else {
+                continue;
+            };
+            cursor = Some((source, source_position + 1));
+
+            let source_token = &source_tokens[source][source_position];
+            // one segment per generated line and source line is enough for stack traces
+            if previous == Some((token.line, source, source_token.line)) {
+                continue;
+            }
+            previous = Some((token.line, source, source_token.line));
+
+            if lines.len() <= token.line as usize {
+                lines.resize(token.line as usize + 1, Vec::new());
+            }
+            lines[token.line as usize].push(Segment {
+                generated_column: token.column,
+                source: source as u32,
+                line: source_token.line,
+                column: source_token.column,
+            });
+        }
+
+        Self {
+            version: 3,
+            file: file.to_string(),
+            sources: sources.iter().map(|(path, _)| path.clone()).collect(),
+            names: Vec::new(),
+            mappings: Self::encode_mappings(&lines),
+        }
+    }
+
+    fn encode_mappings(lines: &[Vec<Segment>]) -> String {
+        let mut mappings = String::new();
+        let (mut source, mut line, mut column) = (0i64, 0i64, 0i64);
+
+        for (line_index, segments) in lines.iter().enumerate() {
+            if line_index > 0 {
+                mappings.push(';');
+            }
+            let mut generated_column = 0i64;
+            for (segment_index, segment) in segments.iter().enumerate() {
+                if segment_index > 0 {
+                    mappings.push(',');
+                }
+                vlq_encode(
+                    segment.generated_column as i64 - generated_column,
+                    &mut mappings,
+                );
+                vlq_encode(segment.source as i64 - source, &mut mappings);
+                vlq_encode(segment.line as i64 - line, &mut mappings);
+                vlq_encode(segment.column as i64 - column, &mut mappings);
+                generated_column = segment.generated_column as i64;
+                source = segment.source as i64;
+                line = segment.line as i64;
+                column = segment.column as i64;
+            }
+        }
+
+        mappings
+    }
+
+    fn decode_mappings(&self) -> anyhow::Result<Vec<Vec<Segment>>> {
+        let (mut source, mut line, mut column) = (0i64, 0i64, 0i64);
+
+        self.mappings
+            .split(';')
+            .map(|encoded_line| {
+                let mut generated_column = 0i64;
+                encoded_line
+                    .split(',')
+                    .filter(|encoded_segment| !encoded_segment.is_empty())
+                    .filter_map(|encoded_segment| match vlq_decode(encoded_segment) {
+                        Ok(values) if values.len() >= 4 => {
+                            generated_column += values[0];
+                            source += values[1];
+                            line += values[2];
+                            column += values[3];
+                            Some(Ok(Segment {
+                                generated_column: generated_column as u32,
+                                source: source as u32,
+                                line: line as u32,
+                                column: column as u32,
+                            }))
+                        }
+                        // segments without a source don't point anywhere
+                        Ok(_) => None,
+                        Err(err) => Some(Err(err)),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The generated code each source accounts for
+    ///
+    /// Generated code is attributed to the last mapping before it, so the bundle's own
+    /// wrapper code is counted towards the module it surrounds. Code before the very first
+    /// mapping is attributed to `None`.
+    pub fn generated_code_per_source(
+        &self,
+        generated: &str,
+    ) -> anyhow::Result<HashMap<Option<String>, String>> {
+        let lines = self.decode_mappings()?;
+        let mut code: HashMap<Option<u32>, String> = HashMap::new();
+        let mut current: Option<u32> = None;
+
+        for (line_index, line) in generated.split('\n').enumerate() {
+            if line_index > 0 {
+                // the newline belongs to whatever is at the end of the previous line
+                code.entry(current).or_default().push('\n');
+            }
+            // columns are counted in characters, not bytes
+            let offsets: Vec<usize> = line
+                .char_indices()
+                .map(|(offset, _)| offset)
+                .chain(std::iter::once(line.len()))
+                .collect();
+            let mut start = 0;
+
+            for segment in lines.get(line_index).into_iter().flatten() {
+                let end = offsets
+                    .get(segment.generated_column as usize)
+                    .copied()
+                    .unwrap_or(line.len())
+                    .max(start);
+                code.entry(current).or_default().push_str(&line[start..end]);
+                start = end;
+                current = Some(segment.source);
+            }
+            code.entry(current).or_default().push_str(&line[start..]);
+        }
+
+        Ok(code
+            .into_iter()
+            .filter(|(_, code)| !code.is_empty())
+            .map(|(source, code)| {
+                (
+                    source.and_then(|source| self.sources.get(source as usize).cloned()),
+                    code,
+                )
+            })
+            .collect())
+    }
+
+    /// How many bytes of the generated code each source accounts for, largest first
+    pub fn generated_bytes_per_source(
+        &self,
+        generated: &str,
+    ) -> anyhow::Result<Vec<(Option<String>, u64)>> {
+        let mut sizes: Vec<(Option<String>, u64)> = self
+            .generated_code_per_source(generated)?
+            .into_iter()
+            .map(|(source, code)| (source, code.len() as u64))
+            .collect();
+        sizes.sort_by(|lhs, rhs| rhs.1.cmp(&lhs.1).then_with(|| lhs.0.cmp(&rhs.0)));
+        Ok(sizes)
+    }
+
+    /// Finds the original location of a (1-based) generated line, and column if known
+    ///
+    /// Lua errors only carry a line, in which case the first mapping of the line is used
+    pub fn lookup(
+        &self,
+        line: u32,
+        column: Option<u32>,
+    ) -> anyhow::Result<Option<OriginalLocation>> {
+        let lines = self.decode_mappings()?;
+        let line_index = line.saturating_sub(1) as usize;
+
+        let segment = match lines.get(line_index) {
+            Some(segments) if !segments.is_empty() => match column {
+                Some(column) => segments
+                    .iter()
+                    .rev()
+                    .find(|segment| segment.generated_column < column)
+                    .or_else(|| segments.first()),
+                None => segments.first(),
+            },
+            // nothing starts on that line, it continues whatever the previous mapping was
+            _ => lines
+                .iter()
+                .take(line_index.min(lines.len()))
+                .rev()
+                .find_map(|segments| segments.last()),
+        };
+
+        Ok(segment.and_then(|segment| {
+            Some(OriginalLocation {
+                source: self.sources.get(segment.source as usize)?.clone(),
+                line: segment.line + 1,
+                column: segment.column + 1,
+            })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_vlq_round_trip() {
+        for value in [0, 1, -1, 15, -16, 16, 1234, -98765] {
+            let mut encoded = String::new();
+            vlq_encode(value, &mut encoded);
+            assert_eq!(vlq_decode(&encoded).unwrap(), vec![value]);
+        }
+    }
+
+    #[test]
+    fn test_tokenize_skips_comments_and_collapses_strings() {
+        let tokens =
+            tokenize("local a = 'x' -- comment\n--[[ long\ncomment ]] print([==[y]==], 0x1F)");
+        let texts: Vec<&str> = tokens.iter().map(|token| token.text.as_str()).collect();
+
+        assert_eq!(
+            texts,
+            vec!["local", "a", "=", "<string>", "print", "(", "<string>", ",", "<number>", ")"]
+        );
+        assert_eq!((tokens[4].line, tokens[4].column), (2, 11));
+    }
+
+    #[test]
+    fn test_generate_maps_bundled_modules_back_to_their_sources() {
+        let main = "local helper = require(\"./helper\")\n\nlocal value = helper.compute(1)\nprint(value)\n";
+        let helper = "local helper = {}\n\nfunction helper.compute(x)\n    return x * 2\nend\n\nreturn helper\n";
+        let generated = "local __BUNDLE_MODULES={cache={}}do do local function __modImpl()local helper={}\nfunction helper.compute(x)return x*2 end return helper end function __BUNDLE_MODULES.a()\nreturn __modImpl()end end end local helper=__BUNDLE_MODULES.a()local value=\nhelper.compute(1)print(value)";
+
+        let source_map = SourceMap::generate(
+            "main.bundle.luau",
+            generated,
+            &[
+                ("main.luau".to_string(), main.to_string()),
+                ("helper.luau".to_string(), helper.to_string()),
+            ],
+            None,
+        );
+
+        let location = source_map.lookup(2, None).unwrap().unwrap();
+        assert_eq!(
+            (location.source.as_str(), location.line),
+            ("helper.luau", 3)
+        );
+
+        let location = source_map.lookup(4, None).unwrap().unwrap();
+        assert_eq!((location.source.as_str(), location.line), ("main.luau", 3));
+
+        let sizes = source_map.generated_bytes_per_source(generated).unwrap();
+        assert_eq!(
+            sizes.iter().map(|(_, size)| *size).sum::<u64>(),
+            generated.len() as u64
+        );
+        assert!(sizes
+            .iter()
+            .any(|(source, _)| source.as_deref() == Some("helper.luau")));
+
+        let round_tripped: SourceMap =
+            serde_json::from_str(&serde_json::to_string(&source_map).unwrap()).unwrap();
+        assert_eq!(round_tripped.mappings, source_map.mappings);
+    }
+
+    #[test]
+    fn test_generate_maps_obfuscated_bundles_back_to_their_sources() {
+        let main = "local helper = require(\"./helper\")\n\nlocal value = helper.compute(1)\nprint(value, \"done\")\n";
+        let helper = "local helper = {}\n\nfunction helper.compute(x)\n    return x * 2\nend\n\nreturn helper\n";
+        let generated = "local function _d(_e,_f)return(string.gsub(_e,\"%x%x\",function(_g)return string.char(bit32.bxor(tonumber(_g,16),_f))end))end local _a={cache={}}do do local function _b()local _c={}\nfunction _c.compute(_h)return _h*2 end return _c end function _a._i()\nreturn _b()end end end local _j=_a._i()local _k=\n_j.compute(1)print(_k,_d(\"636863\",7))";
+        let obfuscation = ObfuscationMap {
+            names: [
+                ("_a", "__BUNDLE_MODULES"),
+                ("_b", "__modImpl"),
+                ("_c", "helper"),
+                ("_h", "x"),
+                ("_j", "helper"),
+                ("_k", "value"),
+            ]
+            .into_iter()
+            .map(|(name, original)| (name.to_string(), original.to_string()))
+            .collect(),
+            string_decoder: Some("_d".to_string()),
+        };
+
+        let source_map = SourceMap::generate(
+            "main.bundle.luau",
+            generated,
+            &[
+                ("main.luau".to_string(), main.to_string()),
+                ("helper.luau".to_string(), helper.to_string()),
+            ],
+            Some(&obfuscation),
+        );
+
+        let location = source_map.lookup(2, None).unwrap().unwrap();
+        assert_eq!(
+            (location.source.as_str(), location.line),
+            ("helper.luau", 3)
+        );
+
+        let location = source_map.lookup(4, None).unwrap().unwrap();
+        assert_eq!((location.source.as_str(), location.line), ("main.luau", 3));
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use darklua_core::nodes::{self, Arguments, Expression, Prefix, Variable};
+use darklua_core::process::{NodeProcessor, Scope, ScopeVisitor};
+use darklua_core::rules::{
+    Context, FlawlessRule, RuleConfiguration, RuleConfigurationError, RuleProperties,
+};
+use darklua_core::ScopedHashMap;
+
+use crate::diagnostics::Diagnostic;
+
+const BUNDLE_MODULES_IDENTIFIER: &str = "__BUNDLE_MODULES";
+const REQUIRE_FUNCTION_IDENTIFIER: &str = "require";
+
+/// Directories of the machine that built the bundle, which have no business in its strings
+fn local_directories() -> Vec<String> {
+    [
+        std::env::current_dir().ok(),
+        std::env::var_os("HOME").map(Into::into),
+        std::env::var_os("USERPROFILE").map(Into::into),
+    ]
+    .into_iter()
+    .flatten()
+    // `/` or `/root` would match far too many strings
+    .filter(|directory: &PathBuf| {
+        directory
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .count()
+            >= 2
+    })
+    .map(|directory| directory.to_string_lossy().replace('\\', "/"))
+    .collect()
+}
+
+/// Whether a string of the bundle is a path of the build machine: anything under one of its
+/// local directories, an absolute path to a Luau file, or a file of the project. URLs never are
+fn is_leaked_path(value: &str, local_directories: &[String], project_directory: &Path) -> bool {
+    let value = value.replace('\\', "/");
+    if value.contains("://") {
+        return false;
+    }
+
+    let is_absolute = value.starts_with('/')
+        || (value.as_bytes().get(1) == Some(&b':') && value.as_bytes()[0].is_ascii_alphabetic());
+    local_directories
+        .iter()
+        .any(|directory| value.contains(directory.as_str()))
+        || (is_absolute && (value.ends_with(".luau") || value.ends_with(".lua")))
+        || (!is_absolute && value.contains('/') && project_directory.join(&value).is_file())
+}
+
+/// Walks a generated bundle and collects everything that shouldn't be in it
+struct BundleValidator<'a> {
+    injected_globals: &'a BTreeSet<String>,
+    local_directories: Vec<String>,
+    project_directory: PathBuf,
+    locals: ScopedHashMap<String, ()>,
+    module_keys: HashSet<String>,
+    violations: Vec<String>,
+}
+
+impl BundleValidator<'_> {
+    fn is_global(&self, name: &str) -> bool {
+        self.locals.get(&name.to_string()).is_none()
+    }
+
+    fn add_module_key(&mut self, key: &str) {
+        if !self.module_keys.insert(key.to_string()) {
+            self.violations.push(format!(
+                "module `{}.{}` is defined more than once",
+                BUNDLE_MODULES_IDENTIFIER, key
+            ));
+        }
+    }
+}
+
+impl NodeProcessor for BundleValidator<'_> {
+    fn process_function_call(&mut self, call: &mut nodes::FunctionCall) {
+        let Prefix::Identifier(identifier) = call.get_prefix() else {
+            return;
+        };
+        if identifier.get_name() != REQUIRE_FUNCTION_IDENTIFIER
+            || !self.is_global(identifier.get_name())
+        {
+            return;
+        }
+
+        let path = match call.get_arguments() {
+            Arguments::String(string) => string.get_string_value(),
+            Arguments::Tuple(tuple) => match tuple.iter_values().next() {
+                Some(Expression::String(string)) => string.get_string_value(),
+                _ => None,
+            },
+            _ => None,
+        };
+        self.violations.push(match path {
+            Some(path) => format!(
+                "require(\"{}\") was not rewritten into {}",
+                path, BUNDLE_MODULES_IDENTIFIER
+            ),
+            None => format!(
+                "a dynamic require was not rewritten into {}",
+                BUNDLE_MODULES_IDENTIFIER
+            ),
+        });
+    }
+
+    fn process_string_expression(&mut self, string: &mut nodes::StringExpression) {
+        if let Some(value) = string.get_string_value() {
+            if is_leaked_path(value, &self.local_directories, &self.project_directory) {
+                self.violations
+                    .push(format!("string \"{}\" leaks a local path", value));
+            }
+        }
+    }
+
+    fn process_variable_expression(&mut self, identifier: &mut nodes::Identifier) {
+        let name = identifier.get_name();
+        if self.injected_globals.contains(name.as_str()) && self.is_global(name) {
+            self.violations.push(format!(
+                "global `{}` is injected for this flow but still referenced",
+                name
+            ));
+        }
+    }
+
+    fn process_function_statement(&mut self, function: &mut nodes::FunctionStatement) {
+        let name = function.get_name();
+        if name.get_name().get_name() == BUNDLE_MODULES_IDENTIFIER
+            && name.get_method().is_none()
+            && name.get_field_names().len() == 1
+        {
+            let key = name.get_field_names()[0].get_name().to_string();
+            self.add_module_key(&key);
+        }
+    }
+
+    fn process_assign_statement(&mut self, assign: &mut nodes::AssignStatement) {
+        let keys = assign
+            .get_variables()
+            .iter()
+            .filter_map(|variable| match variable {
+                Variable::Field(field) => match field.get_prefix() {
+                    Prefix::Identifier(identifier)
+                        if identifier.get_name() == BUNDLE_MODULES_IDENTIFIER =>
+                    {
+                        Some(field.get_field().get_name().to_string())
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for key in keys {
+            self.add_module_key(&key);
+        }
+    }
+}
+
+impl Scope for BundleValidator<'_> {
+    fn push(&mut self) {
+        self.locals.push();
+    }
+    fn pop(&mut self) {
+        self.locals.pop();
+    }
+    fn insert(&mut self, identifier: &mut String) {
+        self.locals.insert(identifier.clone(), ());
+    }
+    fn insert_local(&mut self, identifier: &mut String, _: Option<&mut nodes::Expression>) {
+        self.locals.insert(identifier.clone(), ());
+    }
+    fn insert_local_function(&mut self, function: &mut nodes::LocalFunctionStatement) {
+        self.locals.insert(function.get_name().to_string(), ());
+    }
+    fn insert_self(&mut self) {
+        self.locals.insert("self".to_string(), ());
+    }
+}
+
+/// Everything that shouldn't be in the bundle `block`
+fn find_violations(block: &mut nodes::Block, injected_globals: &BTreeSet<String>) -> Vec<String> {
+    let mut validator = BundleValidator {
+        injected_globals,
+        local_directories: local_directories(),
+        project_directory: std::env::current_dir().unwrap_or_default(),
+        locals: ScopedHashMap::default(),
+        module_keys: HashSet::new(),
+        violations: Vec::new(),
+    };
+    ScopeVisitor::visit_block(block, &mut validator);
+    validator.violations
+}
+
+/// Filled in by the validation rule while darklua processes the bundle
+pub type SharedViolations = Arc<Mutex<Vec<String>>>;
+
+/// darklua rule checking the whole bundle for a require that wasn't rewritten, a local path
+/// leaked into a string, a module defined twice or an injected global still referenced. It runs
+/// right before the obfuscation, which renames `__BUNDLE_MODULES` and encodes strings
+#[derive(Debug)]
+pub struct ValidateBundle {
+    injected_globals: BTreeSet<String>,
+    violations: SharedViolations,
+}
+
+impl ValidateBundle {
+    pub fn new(injected_globals: BTreeSet<String>) -> Self {
+        Self {
+            injected_globals,
+            violations: SharedViolations::default(),
+        }
+    }
+
+    pub fn violations(&self) -> SharedViolations {
+        self.violations.clone()
+    }
+}
+
+impl FlawlessRule for ValidateBundle {
+    fn flawless_process(&self, block: &mut nodes::Block, _: &Context) {
+        *self.violations.lock().unwrap() = find_violations(block, &self.injected_globals);
+    }
+}
+
+impl RuleConfiguration for ValidateBundle {
+    fn configure(&mut self, _: RuleProperties) -> Result<(), RuleConfigurationError> {
+        Ok(())
+    }
+
+    fn get_name(&self) -> &'static str {
+        "validate_bundle"
+    }
+
+    fn serialize_to_properties(&self) -> RuleProperties {
+        RuleProperties::new()
+    }
+}
+
+/// Re-parses the generated bundle `content`, to be written to `output`, and fails if it isn't
+/// valid Luau or if the validation rule found `violations` in it
+pub fn validate_bundle(content: &str, output: &Path, violations: &[String]) -> Result<()> {
+    darklua_core::Parser::default()
+        .parse(content)
+        .map_err(|e| {
+            Diagnostic::error(
+                "validation",
+                format!("generated bundle doesn't parse: {}", e),
+            )
+            .with_location_from_message()
+            .with_file(output)
+            .emit();
+            anyhow::anyhow!("{} is not valid Luau", output.display())
+        })?;
+
+    if !violations.is_empty() {
+        for violation in violations {
+            Diagnostic::error("validation", violation.as_str())
+                .with_file(output)
+                .emit();
+        }
+        anyhow::bail!(
+            "{} failed validation with {} problem(s)",
+            output.display(),
+            violations.len()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_violations() {
+        let mut block = darklua_core::Parser::default()
+            .parse(
+                r#"
+local __BUNDLE_MODULES = { cache = {} }
+function __BUNDLE_MODULES.a()
+    return require("./b")
+end
+__BUNDLE_MODULES.a = function() end
+local function log(API_URL)
+    print(API_URL)
+end
+print(API_URL, "/opt/build/src/shared/http.luau")
+"#,
+            )
+            .unwrap();
+
+        let violations = find_violations(&mut block, &BTreeSet::from(["API_URL".to_string()]));
+        assert_eq!(
+            violations,
+            vec![
+                "require(\"./b\") was not rewritten into __BUNDLE_MODULES".to_string(),
+                "module `__BUNDLE_MODULES.a` is defined more than once".to_string(),
+                "global `API_URL` is injected for this flow but still referenced".to_string(),
+                "string \"/opt/build/src/shared/http.luau\" leaks a local path".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_leaked_path() {
+        let local_directories = vec!["/home/alice/flows".to_string()];
+        let project_directory = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let is_leaked = |value: &str| is_leaked_path(value, &local_directories, project_directory);
+
+        assert!(is_leaked("/home/alice/flows/src/login.luau"));
+        assert!(is_leaked("/opt/build/src/shared/http.luau"));
+        assert!(is_leaked("C:\\build\\src\\shared\\http.lua"));
+        assert!(is_leaked("./src/main.rs"));
+        assert!(is_leaked("src\\config.rs"));
+        assert!(!is_leaked("./src/shared/http.luau"));
+        assert!(!is_leaked("https://cdn.example.com/x.lua"));
+        assert!(!is_leaked("/v1/sessions"));
+        assert!(!is_leaked("config.luau"));
+        assert!(!is_leaked("https://example.com"));
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use tracing::info;
+
+use crate::commands::bundle::lock::HashesLock;
+use crate::commands::bundle::manifest::MANIFEST_FILE_NAME;
+use crate::commands::bundle::reproducible::REPRODUCIBLE_DIRECTORY_PREFIX;
+use crate::commands::bundle::variant_alias;
+use crate::config::{self, Config};
+
+const BUNDLE_EXTENSION: &str = ".bundle.luau";
+
+/// Alias of a file `bundle` writes to the output directory: the bundle itself, its source map,
+/// its signature or the unfinished bundle of an interrupted build
+fn generated_alias(file_name: &str) -> Option<&str> {
+    [".map", ".sig", ".tmp", ""].iter().find_map(|suffix| {
+        file_name
+            .strip_suffix(suffix)?
+            .strip_suffix(BUNDLE_EXTENSION)
+            .filter(|alias| !alias.is_empty())
+    })
+}
+
+/// Aliases of every bundle the configuration produces, variants included
+fn expected_aliases(config: &Config) -> BTreeSet<String> {
+    config
+        .platforms
+        .iter()
+        .flat_map(|platform| &platform.flows)
+        .flat_map(|flow| {
+            std::iter::once(flow.alias.clone()).chain(
+                flow.variants
+                    .iter()
+                    .flatten()
+                    .map(|variant| variant_alias(&flow.alias, variant)),
+            )
+        })
+        .collect()
+}
+
+/// Generated files in the output directory, with their alias
+fn generated_files(output_directory: &Path) -> Result<Vec<(PathBuf, String)>> {
+    if !output_directory.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(output_directory)? {
+        let path = entry?.path();
+        let alias = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(generated_alias);
+        if let (Some(alias), true) = (alias, path.is_file()) {
+            files.push((path.clone(), alias.to_string()));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn remove_file(path: &Path) -> Result<bool> {
+    match std::fs::remove_file(path) {
+        Ok(()) => {
+            println!("Removed {}", path.display());
+            Ok(true)
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(anyhow::anyhow!(
+            "Failed to remove {}: {}",
+            path.display(),
+            err
+        )),
+    }
+}
+
+/// Deletes the bundles, source maps and signatures of flows that aren't configured anymore
+pub fn prune(config: &Config) -> Result<usize> {
+    let expected = expected_aliases(config);
+    let mut removed = 0;
+
+    for (path, alias) in generated_files(Path::new(&config.settings.output_directory))? {
+        if !expected.contains(&alias) && remove_file(&path)? {
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Removes everything `bundle` and `compute-versions` generate: bundles, source maps,
+/// signatures, the manifest, the lock files and leftover reproducibility builds. With a
+/// profile, versions.lock is left to the other builds
+pub fn clean(config_path: &str, profile: Option<&str>) -> Result<()> {
+    let config = config::Config::from_file_with_profile(config_path, profile)?;
+    let output_directory = Path::new(&config.settings.output_directory);
+
+    let mut config_path_dir_buf = PathBuf::from(config_path);
+    config_path_dir_buf.pop();
+
+    let mut removed = 0;
+    let generated = generated_files(output_directory)?
+        .into_iter()
+        .map(|(path, _)| path)
+        .chain([
+            output_directory.join(MANIFEST_FILE_NAME),
+            HashesLock::path(&config_path_dir_buf, profile),
+        ])
+        // versions.lock is shared by every profile, only a plain `clean` removes it
+        .chain(
+            profile
+                .is_none()
+                .then(|| config_path_dir_buf.join("versions.lock")),
+        );
+    for path in generated {
+        if remove_file(&path)? {
+            removed += 1;
+        }
+    }
+
+    if output_directory.is_dir() {
+        for entry in std::fs::read_dir(output_directory)? {
+            let path = entry?.path();
+            let is_reproducible_build = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(REPRODUCIBLE_DIRECTORY_PREFIX));
+            if is_reproducible_build && path.is_dir() {
+                std::fs::remove_dir_all(&path)?;
+                println!("Removed {}", path.display());
+                removed += 1;
+            }
+        }
+    }
+
+    // only drop the output directory if nothing else lives in it
+    if output_directory.is_dir() && std::fs::read_dir(output_directory)?.next().is_none() {
+        std::fs::remove_dir(output_directory)?;
+    }
+
+    info!("Removed {} generated file(s)", removed);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_generated_alias() {
+        assert_eq!(generated_alias("login.bundle.luau"), Some("login"));
+        assert_eq!(generated_alias("login.bundle.luau.map"), Some("login"));
+        assert_eq!(
+            generated_alias("login@26-.bundle.luau.sig"),
+            Some("login@26-")
+        );
+        assert_eq!(generated_alias("login.bundle.luau.tmp"), Some("login"));
+        assert_eq!(generated_alias("login.luau"), None);
+        assert_eq!(generated_alias(".bundle.luau"), None);
+        assert_eq!(generated_alias("manifest.json"), None);
+    }
+}
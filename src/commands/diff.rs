@@ -0,0 +1,582 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::Args;
+use darklua_core::Resources;
+use uuid::Uuid;
+
+use crate::commands::bundle::lock::{HashesLock, HASHES_LOCK_FILE_NAME};
+use crate::commands::bundle::manifest::{Manifest, ManifestFlow, MANIFEST_FILE_NAME};
+use crate::commands::bundle::size_report::format_size;
+use crate::commands::bundle::{
+    build_flow, collect_flow_modules, create_options_in, display_path, injected_global_values,
+    sha256_hex, write_source_map,
+};
+use crate::commands::package::archive::{read_archive, ArchiveFormat};
+use crate::commands::version::sdk_version::SdkVersionOut;
+use crate::commands::version::{read_version_file, version_file_path};
+use crate::config::{self, Flow, SimplePlatform};
+use crate::paths::relative_to;
+
+/// Above this many line pairs, a changed module is reported as a whole instead of by function
+const MAX_LINE_DIFF_SIZE: usize = 4_000_000;
+
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    /// Alias of the flow to compare
+    pub alias: String,
+
+    /// What to compare the working tree against: a project directory, an output directory,
+    /// a release archive from `package` or a git ref
+    #[arg(long, value_name = "DIR|ARCHIVE|GIT_REF", default_value = "HEAD")]
+    pub against: String,
+}
+
+#[derive(Debug, Default)]
+struct ModuleSnapshot {
+    /// SHA-256 of the source file
+    source_hash: Option<String>,
+    /// Code of the module in a readable build of the bundle
+    code: Option<String>,
+}
+
+/// A flow's bundle as built from one side of the diff
+#[derive(Debug, Default)]
+struct FlowSnapshot {
+    size: u64,
+    sdk_version: Option<SdkVersionOut>,
+    /// `None` when the side doesn't record them, like archives
+    globals: Option<BTreeMap<String, serde_json::Value>>,
+    modules: BTreeMap<String, ModuleSnapshot>,
+}
+
+fn temporary_directory() -> PathBuf {
+    std::env::temp_dir().join(format!("opacity-cli-diff-{}", Uuid::new_v4()))
+}
+
+/// Re-roots the paths of a config read from `root`, which are relative to its directory
+fn reroot_config(config: &mut config::Config, root: &Path) {
+    for flow in config
+        .platforms
+        .iter_mut()
+        .flat_map(|platform| platform.flows.iter_mut())
+    {
+        flow.path = root.join(&flow.path).to_string_lossy().to_string();
+    }
+    for target in config
+        .settings
+        .aliases
+        .iter_mut()
+        .flat_map(|aliases| aliases.values_mut())
+    {
+        *target = root.join(&*target).to_string_lossy().to_string();
+    }
+}
+
+/// Builds the flow in `root` twice: as configured for its size, then with the readable
+/// generator to compare its modules
+fn snapshot_project(
+    root: &Path,
+    config_file: &str,
+    profile: Option<&str>,
+    alias: &str,
+) -> Result<Option<FlowSnapshot>> {
+    let config_path = root.join(config_file).to_string_lossy().to_string();
+    let build_directory = temporary_directory();
+
+    let snapshot = (|| -> Result<Option<FlowSnapshot>> {
+        let mut config = config::Config::from_file_with_profile(&config_path, profile)?;
+        reroot_config(&mut config, root);
+        let Some((platform, flow)) = config.platforms.iter().find_map(|platform| {
+            platform
+                .flows
+                .iter()
+                .find(|flow| flow.alias == alias)
+                .map(|flow| (SimplePlatform::from(platform), flow.clone()))
+        }) else {
+            return Ok(None);
+        };
+
+        let resources = Resources::from_file_system();
+        let version_file = if version_file_path(&config_path).exists() {
+            Some(read_version_file(&config_path)?)
+        } else {
+            None
+        };
+        let flow_modules =
+            collect_flow_modules(&resources, &config.settings, &flow, version_file.as_ref())?;
+
+        let output = build_flow(
+            &resources,
+            create_options_in(
+                &config,
+                &platform,
+                &flow,
+                &flow_modules,
+                &build_directory.join("configured"),
+                None,
+            )?,
+        )?;
+        let size = std::fs::metadata(&output)?.len();
+        let globals = injected_global_values(&config.settings, &platform, &flow)?;
+
+        // build info changes with every commit, leaving it out keeps it out of the diff, and
+        // obfuscated names would make every function look changed
+        config.settings.generator = Some(toml::Value::String("readable".to_string()));
+        config.settings.build_info = None;
+        config.settings.obfuscation = None;
+        let readable_flow = Flow {
+            obfuscation: None,
+            ..flow.clone()
+        };
+        let output = build_flow(
+            &resources,
+            create_options_in(
+                &config,
+                &platform,
+                &readable_flow,
+                &flow_modules,
+                &build_directory.join("readable"),
+                None,
+            )?,
+        )?;
+        let mut code = write_source_map(&resources, &flow_modules, &output, None)?
+            .generated_code_per_source(&std::fs::read_to_string(&output)?)?;
+
+        let modules = flow_modules
+            .modules
+            .iter()
+            .map(|module| {
+                let snapshot = ModuleSnapshot {
+                    source_hash: std::fs::read(module)
+                        .ok()
+                        .map(|content| sha256_hex(&content)),
+                    code: Some(code.remove(&Some(display_path(module))).unwrap_or_default()),
+                };
+                // both sides are keyed like `bundle` records them, relative to the project
+                (display_path(&relative_to(module, root)), snapshot)
+            })
+            .collect();
+
+        Ok(Some(FlowSnapshot {
+            size,
+            sdk_version: flow_modules.sdk_version,
+            globals: Some(globals),
+            modules,
+        }))
+    })();
+
+    let _ = std::fs::remove_dir_all(&build_directory);
+    snapshot
+}
+
+/// What `bundle` recorded about the flow, for sides that only have the bundles. Its modules are
+/// relative to the config directory, like the sources of hashes.lock and the modules of
+/// `snapshot_project`. Without a readable build, a changed module is only reported as such, not
+/// by function
+fn snapshot_manifest(
+    manifest: &Manifest,
+    lock: Option<&HashesLock>,
+    alias: &str,
+) -> Option<FlowSnapshot> {
+    let ManifestFlow {
+        size,
+        computed_sdk_version,
+        modules,
+        ..
+    } = manifest.get_flow(alias)?;
+    let sources = lock
+        .and_then(|lock| lock.flows.get(alias))
+        .map(|hashes| hashes.sources.clone())
+        .unwrap_or_default();
+
+    Some(FlowSnapshot {
+        size: *size,
+        sdk_version: computed_sdk_version.clone(),
+        globals: None,
+        modules: modules
+            .iter()
+            .map(|module| {
+                let snapshot = ModuleSnapshot {
+                    source_hash: sources.get(module).cloned(),
+                    code: None,
+                };
+                (module.clone(), snapshot)
+            })
+            .collect(),
+    })
+}
+
+/// Extracts the tree of a git ref, limited to `directory` like `git archive` run there does
+fn checkout_git_ref(git_ref: &str, directory: &Path, destination: &Path) -> Result<()> {
+    let output = std::process::Command::new("git")
+        .args(["archive", "--format=tar", git_ref])
+        .current_dir(directory)
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "`{}` is not a directory, an archive or a git ref: {}",
+            git_ref,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    std::fs::create_dir_all(destination)?;
+    tar::Archive::new(output.stdout.as_slice()).unpack(destination)?;
+    Ok(())
+}
+
+fn snapshot_against(
+    against: &str,
+    config_dir: &Path,
+    config_file: &str,
+    profile: Option<&str>,
+    alias: &str,
+) -> Result<Option<FlowSnapshot>> {
+    let path = Path::new(against);
+
+    if path.is_dir() && path.join(config_file).is_file() {
+        return snapshot_project(path, config_file, profile, alias);
+    }
+    if path.is_dir() {
+        let manifest = Manifest::read(against)?.ok_or_else(|| {
+            anyhow::anyhow!(
+                "{} has neither {} nor {}",
+                against,
+                config_file,
+                MANIFEST_FILE_NAME
+            )
+        })?;
+        return Ok(snapshot_manifest(&manifest, None, alias));
+    }
+    if path.is_file() {
+        ArchiveFormat::from_path(path)?;
+        let files = read_archive(path)?
+            .into_iter()
+            .map(|entry| (entry.name, entry.content))
+            .collect::<BTreeMap<_, _>>();
+        let manifest: Manifest = serde_json::from_slice(
+            files
+                .get(MANIFEST_FILE_NAME)
+                .ok_or_else(|| anyhow::anyhow!("No {} in {}", MANIFEST_FILE_NAME, against))?,
+        )?;
+        let lock = files
+            .get(HASHES_LOCK_FILE_NAME)
+            .map(|content| HashesLock::parse(&String::from_utf8_lossy(content), Path::new("")))
+            .transpose()?;
+        return Ok(snapshot_manifest(&manifest, lock.as_ref(), alias));
+    }
+
+    let checkout = temporary_directory();
+    let snapshot = checkout_git_ref(against, config_dir, &checkout)
+        .and_then(|_| snapshot_project(&checkout, config_file, profile, alias));
+    let _ = std::fs::remove_dir_all(&checkout);
+    snapshot
+}
+
+/// Name of the function a line of readable Luau declares, if it declares one
+fn function_name(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    let name = if let Some(rest) = line
+        .strip_prefix("local function ")
+        .or_else(|| line.strip_prefix("function "))
+    {
+        rest.split('(').next()?
+    } else {
+        let (target, value) = line.split_once('=')?;
+        if !value.trim_start().starts_with("function") {
+            return None;
+        }
+        target.trim_start_matches("local ")
+    };
+    Some(name.trim()).filter(|name| !name.is_empty())
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// The function each line of readable Luau belongs to, `None` at the top level of a module
+fn enclosing_functions(lines: &[&str]) -> Vec<Option<String>> {
+    let mut stack: Vec<(usize, String)> = Vec::new();
+    lines
+        .iter()
+        .map(|line| {
+            let (indent, trimmed) = (indentation(line), line.trim());
+            if !trimmed.is_empty() {
+                while let Some((depth, _)) = stack.last() {
+                    if indent > *depth || (indent == *depth && trimmed == "end") {
+                        break;
+                    }
+                    stack.pop();
+                }
+            }
+            if let Some(name) = function_name(line) {
+                stack.push((indent, name.to_string()));
+            }
+
+            let function = stack
+                .iter()
+                .rev()
+                // the bundle's own wrappers don't mean anything to reviewers
+                .find(|(_, name)| !name.starts_with("__"))
+                .map(|(_, name)| name.clone());
+            // the closing `end` still belongs to the function
+            if stack
+                .last()
+                .is_some_and(|(depth, _)| indent == *depth && trimmed == "end")
+            {
+                stack.pop();
+            }
+            function
+        })
+        .collect()
+}
+
+/// Lines of `before` and `after` that aren't part of their longest common subsequence
+fn changed_lines(before: &[&str], after: &[&str]) -> (Vec<usize>, Vec<usize>) {
+    let (n, m) = (before.len(), after.len());
+    let mut lengths = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if before[i] == after[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && before[i] == after[j] {
+            i += 1;
+            j += 1;
+        } else if j < m && (i == n || lengths[i][j + 1] >= lengths[i + 1][j]) {
+            added.push(j);
+            j += 1;
+        } else {
+            removed.push(i);
+            i += 1;
+        }
+    }
+    (removed, added)
+}
+
+/// Functions whose code differs between two readable builds of a module
+fn changed_functions(before: &str, after: &str) -> BTreeSet<String> {
+    let before = before.lines().collect::<Vec<_>>();
+    let after = after.lines().collect::<Vec<_>>();
+    if before.len().saturating_mul(after.len()) > MAX_LINE_DIFF_SIZE {
+        return BTreeSet::from(["(whole module)".to_string()]);
+    }
+
+    let (removed, added) = changed_lines(&before, &after);
+    let (before_functions, after_functions) =
+        (enclosing_functions(&before), enclosing_functions(&after));
+    removed
+        .iter()
+        .map(|index| &before_functions[*index])
+        .chain(added.iter().map(|index| &after_functions[*index]))
+        .map(|function| {
+            function
+                .clone()
+                .unwrap_or_else(|| "(top level)".to_string())
+        })
+        .collect()
+}
+
+fn format_sdk_version(sdk_version: &Option<SdkVersionOut>) -> String {
+    match sdk_version {
+        None => "unknown".to_string(),
+        Some(SdkVersionOut {
+            min_sdk_version,
+            max_sdk_version: Some(max_sdk_version),
+        }) => format!("{}-{}", min_sdk_version, max_sdk_version),
+        Some(SdkVersionOut {
+            min_sdk_version, ..
+        }) => format!("{}+", min_sdk_version),
+    }
+}
+
+fn print_diff(before: &FlowSnapshot, after: &FlowSnapshot) {
+    let delta = after.size as i64 - before.size as i64;
+    println!(
+        "  Size: {} -> {} ({}{})",
+        format_size(before.size),
+        format_size(after.size),
+        if delta < 0 { "-" } else { "+" },
+        format_size(delta.unsigned_abs())
+    );
+
+    let (before_sdk, after_sdk) = (
+        format_sdk_version(&before.sdk_version),
+        format_sdk_version(&after.sdk_version),
+    );
+    if before_sdk != after_sdk {
+        println!("  SDK range: {} -> {}", before_sdk, after_sdk);
+    } else {
+        println!("  SDK range: {} (unchanged)", after_sdk);
+    }
+
+    match (&before.globals, &after.globals) {
+        (Some(before_globals), Some(after_globals)) => {
+            let identifiers = before_globals
+                .keys()
+                .chain(after_globals.keys())
+                .collect::<BTreeSet<_>>();
+            let changes = identifiers
+                .into_iter()
+                .filter_map(|identifier| {
+                    match (
+                        before_globals.get(identifier),
+                        after_globals.get(identifier),
+                    ) {
+                        (Some(before), Some(after)) if before != after => {
+                            Some(format!("~ {}: {} -> {}", identifier, before, after))
+                        }
+                        (Some(before), None) => Some(format!("- {}: {}", identifier, before)),
+                        (None, Some(after)) => Some(format!("+ {}: {}", identifier, after)),
+                        _ => None,
+                    }
+                })
+                .collect::<Vec<_>>();
+            if !changes.is_empty() {
+                println!("  Injected globals:");
+                for change in changes {
+                    println!("    {}", change);
+                }
+            }
+        }
+        _ => println!("  Injected globals: not recorded on both sides"),
+    }
+
+    let modules = before
+        .modules
+        .keys()
+        .chain(after.modules.keys())
+        .collect::<BTreeSet<_>>();
+    let mut lines = Vec::new();
+    for module in modules {
+        match (before.modules.get(module), after.modules.get(module)) {
+            (None, Some(_)) => lines.push(format!("+ {}", module)),
+            (Some(_), None) => lines.push(format!("- {}", module)),
+            (Some(before), Some(after)) => {
+                if let (Some(before_code), Some(after_code)) = (&before.code, &after.code) {
+                    if before_code != after_code {
+                        let functions = changed_functions(before_code, after_code);
+                        let delta = after_code.len() as i64 - before_code.len() as i64;
+                        lines.push(format!(
+                            "~ {} ({:+} B): {}",
+                            module,
+                            delta,
+                            functions.into_iter().collect::<Vec<_>>().join(", ")
+                        ));
+                    }
+                } else if before.source_hash != after.source_hash {
+                    lines.push(format!("~ {} (source changed)", module));
+                }
+            }
+            (None, None) => {}
+        }
+    }
+
+    if lines.is_empty() {
+        println!("  Modules: unchanged");
+    } else {
+        println!("  Modules:");
+        for line in lines {
+            println!("    {}", line);
+        }
+    }
+}
+
+/// Compares the bundle of a flow in the working tree with the same flow built elsewhere:
+/// another directory, a release archive or a git ref
+pub fn diff(config_path: &str, profile: Option<&str>, args: &DiffArgs) -> Result<()> {
+    let config_path = Path::new(config_path);
+    let config_dir = config_path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let config_file = config_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| anyhow::anyhow!("Invalid config path {}", config_path.display()))?;
+
+    let after = snapshot_project(config_dir, &config_file, profile, &args.alias)?;
+    let before = snapshot_against(
+        &args.against,
+        config_dir,
+        &config_file,
+        profile,
+        &args.alias,
+    )?;
+
+    println!("{}: {} -> working tree", args.alias, args.against);
+    match (before, after) {
+        (None, None) => anyhow::bail!("No flow with alias {} on either side", args.alias),
+        (Some(before), None) => {
+            println!("  Removed from the working tree");
+            print_diff(&before, &FlowSnapshot::default());
+        }
+        (None, Some(after)) => {
+            println!("  New in the working tree");
+            print_diff(&FlowSnapshot::default(), &after);
+        }
+        (Some(before), Some(after)) => print_diff(&before, &after),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_changed_functions() {
+        let before = "local function __modImpl()
+    local http = {}
+    function http.get(url)
+        return request(\"GET\", url)
+    end
+    function http.post(url, body)
+        return request(\"POST\", url, body)
+    end
+    return http
+end";
+        let after = "local function __modImpl()
+    local http = {}
+    function http.get(url)
+        return request(\"GET\", url, { timeout = 10 })
+    end
+    function http.post(url, body)
+        return request(\"POST\", url, body)
+    end
+    http.VERSION = 2
+    return http
+end";
+
+        assert_eq!(
+            changed_functions(before, after),
+            BTreeSet::from(["(top level)".to_string(), "http.get".to_string()])
+        );
+        assert!(changed_functions(before, before).is_empty());
+    }
+
+    #[test]
+    fn test_function_name() {
+        assert_eq!(function_name("  function http.get(url)"), Some("http.get"));
+        assert_eq!(function_name("local function helper()"), Some("helper"));
+        assert_eq!(
+            function_name("local handler = function(event)"),
+            Some("handler")
+        );
+        assert_eq!(function_name("local x = 1"), None);
+        assert_eq!(function_name("if a == functionName then"), None);
+    }
+}
@@ -0,0 +1,194 @@
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use ed25519_dalek::pkcs8::spki::der::pem::LineEnding;
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Subcommand)]
+pub enum KeysCommands {
+    /// Generate an Ed25519 key pair to sign bundles with
+    Generate {
+        /// Base path of the key pair, writes `<out>.key` (private) and `<out>.pub` (public)
+        #[arg(short, long, default_value = "opacity")]
+        out: String,
+
+        /// Overwrite existing keys
+        #[arg(short, long)]
+        force: bool,
+    },
+}
+
+/// Where the detached signature of a bundle lives: `<alias>.bundle.luau.sig`
+pub fn signature_path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+pub fn load_signing_key(path: &str) -> Result<SigningKey> {
+    let pem = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read private key {}", path))?;
+    SigningKey::from_pkcs8_pem(&pem)
+        .map_err(|e| anyhow::anyhow!("Invalid Ed25519 private key {}: {}", path, e))
+}
+
+pub fn load_verifying_key(path: &str) -> Result<VerifyingKey> {
+    let pem = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read public key {}", path))?;
+    VerifyingKey::from_public_key_pem(&pem)
+        .map_err(|e| anyhow::anyhow!("Invalid Ed25519 public key {}: {}", path, e))
+}
+
+/// Signs `content` and returns the hex-encoded signature
+pub fn sign(signing_key: &SigningKey, content: &[u8]) -> String {
+    signing_key
+        .sign(content)
+        .to_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Bytes of a hex string, `None` unless it is made of pairs of hex digits only
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 || !text.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&text[index..index + 2], 16).ok())
+        .collect()
+}
+
+/// Checks a hex-encoded signature of `content`
+pub fn verify_signature(
+    verifying_key: &VerifyingKey,
+    content: &[u8],
+    signature: &str,
+) -> Result<()> {
+    let bytes = decode_hex(signature.trim())
+        .and_then(|bytes| <[u8; 64]>::try_from(bytes).ok())
+        .ok_or_else(|| anyhow::anyhow!("Malformed signature"))?;
+
+    verifying_key
+        .verify(content, &Signature::from_bytes(&bytes))
+        .map_err(|_| anyhow::anyhow!("Signature does not match"))
+}
+
+fn generate(out: &str, force: bool) -> Result<()> {
+    let private_key_path = format!("{}.key", out);
+    let public_key_path = format!("{}.pub", out);
+
+    if !force {
+        for path in [&private_key_path, &public_key_path] {
+            if Path::new(path).exists() {
+                anyhow::bail!("{} already exists, use --force to overwrite it", path);
+            }
+        }
+    }
+
+    let signing_key = SigningKey::generate(&mut rand_core::OsRng);
+    let private_key = signing_key
+        .to_pkcs8_pem(LineEnding::LF)
+        .map_err(|e| anyhow::anyhow!("Failed to encode private key: {}", e))?;
+    let public_key = signing_key
+        .verifying_key()
+        .to_public_key_pem(LineEnding::LF)
+        .map_err(|e| anyhow::anyhow!("Failed to encode public key: {}", e))?;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        // the private key is only meant to be read by its owner
+        options.mode(0o600);
+    }
+    let mut file = options.open(&private_key_path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        // `mode` only applies to new files, an overwritten key keeps its permissions otherwise
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(private_key.as_bytes())?;
+    std::fs::write(&public_key_path, public_key)?;
+
+    println!("Private key: {} (keep it secret)", private_key_path);
+    println!("Public key: {}", public_key_path);
+
+    Ok(())
+}
+
+pub fn keys(command: &KeysCommands) -> Result<()> {
+    match command {
+        KeysCommands::Generate { out, force } => generate(out, *force),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::paths::TestDirectory;
+
+    #[test]
+    fn test_sign_and_verify_signature() {
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let verifying_key = signing_key.verifying_key();
+        let signature = sign(&signing_key, b"print(1)");
+        assert_eq!(signature.len(), 128);
+
+        assert!(verify_signature(&verifying_key, b"print(1)", &signature).is_ok());
+        let with_newline = format!("{}\n", signature);
+        assert!(verify_signature(&verifying_key, b"print(1)", &with_newline).is_ok());
+
+        let tampered = verify_signature(&verifying_key, b"print(2)", &signature).unwrap_err();
+        assert_eq!(tampered.to_string(), "Signature does not match");
+
+        let other_key = SigningKey::from_bytes(&[8; 32]).verifying_key();
+        let wrong_key = verify_signature(&other_key, b"print(1)", &signature).unwrap_err();
+        assert_eq!(wrong_key.to_string(), "Signature does not match");
+
+        for malformed in [
+            &signature[..127],
+            &signature[..126],
+            format!("zz{}", &signature[2..]).as_str(),
+            format!("+f{}", &signature[2..]).as_str(),
+            format!("é{}", &signature[2..]).as_str(),
+            "",
+        ] {
+            let error = verify_signature(&verifying_key, b"print(1)", malformed).unwrap_err();
+            assert_eq!(error.to_string(), "Malformed signature");
+        }
+    }
+
+    #[test]
+    fn test_generate_and_load_keys() -> Result<()> {
+        let directory = TestDirectory::create();
+        let out = directory.join("opacity").to_string_lossy().to_string();
+        let private_key_path = format!("{}.key", out);
+        let public_key_path = format!("{}.pub", out);
+
+        generate(&out, false)?;
+        assert!(generate(&out, false).is_err());
+        generate(&out, true)?;
+
+        let signing_key = load_signing_key(&private_key_path)?;
+        let verifying_key = load_verifying_key(&public_key_path)?;
+        assert_eq!(signing_key.verifying_key(), verifying_key);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&private_key_path)?.permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        std::fs::write(&private_key_path, "not a key")?;
+        assert!(load_signing_key(&private_key_path).is_err());
+        assert!(load_verifying_key(&private_key_path).is_err());
+        assert!(load_signing_key(&directory.join("missing.key").to_string_lossy()).is_err());
+        Ok(())
+    }
+}
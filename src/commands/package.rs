@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use clap::Args;
+use tracing::info;
+
+use crate::commands::bundle::build_info::{read_git_commit, source_date_epoch};
+use crate::commands::bundle::lock::{HashesLock, HASHES_LOCK_FILE_NAME};
+use crate::commands::bundle::manifest::{Manifest, MANIFEST_FILE_NAME};
+use crate::commands::bundle::{bundle, sha256_hex, BundleArgs};
+use crate::commands::keys::{
+    load_signing_key, load_verifying_key, signature_path, verify_signature,
+};
+use crate::config;
+
+pub mod archive;
+
+use archive::{read_archive, write_archive, ArchiveEntry, ArchiveFormat};
+
+const VERSIONS_LOCK_FILE_NAME: &str = "versions.lock";
+
+#[derive(Args, Debug)]
+pub struct PackageArgs {
+    /// Archive format (defaults to the extension of `--output`, or tar.gz)
+    #[arg(long, value_enum)]
+    pub format: Option<ArchiveFormat>,
+
+    /// Version in the archive name (defaults to the current git commit)
+    #[arg(long, value_name = "VERSION")]
+    pub release: Option<String>,
+
+    /// Where to write the archive (defaults to `opacity-bundles-<version>.<format>` next to
+    /// the config)
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Package the bundles already in the output directory instead of bundling again
+    #[arg(long)]
+    pub no_build: bool,
+
+    /// Sign every bundle with this Ed25519 private key before packaging
+    #[arg(long, value_name = "KEY")]
+    pub sign: Option<String>,
+}
+
+/// Bundle files of the manifest, variants included, with their expected SHA-256 and signature
+fn manifest_bundles(manifest: &Manifest) -> Vec<(&str, &str, Option<&str>)> {
+    manifest
+        .flows
+        .iter()
+        .flat_map(|flow| {
+            std::iter::once((
+                flow.bundle.as_str(),
+                flow.sha256.as_str(),
+                flow.signature.as_deref(),
+            ))
+            .chain(flow.variants.iter().map(|variant| {
+                (
+                    variant.bundle.as_str(),
+                    variant.sha256.as_str(),
+                    variant.signature.as_deref(),
+                )
+            }))
+        })
+        .collect()
+}
+
+/// `--format`, or the format of the `--output` extension, tar.gz without either. They must
+/// agree, `inspect` reads archives by their extension
+fn archive_format(format: Option<ArchiveFormat>, output: Option<&Path>) -> Result<ArchiveFormat> {
+    let Some(output) = output else {
+        return Ok(format.unwrap_or_default());
+    };
+    let output_format = ArchiveFormat::from_path(output)?;
+    match format {
+        Some(format) if format != output_format => anyhow::bail!(
+            "--format {} doesn't match the extension of {}",
+            format.extension(),
+            output.display()
+        ),
+        _ => Ok(output_format),
+    }
+}
+
+fn read_entry(name: &str, path: &Path) -> Result<ArchiveEntry> {
+    Ok(ArchiveEntry {
+        name: name.to_string(),
+        content: std::fs::read(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?,
+    })
+}
+
+/// Writes a release archive with every bundle of the manifest, their signatures, the manifest
+/// and the lock files
+pub fn package(config_path: &str, profile: Option<&str>, args: &PackageArgs) -> Result<()> {
+    let format = archive_format(args.format, args.output.as_deref().map(Path::new))?;
+    if !args.no_build {
+        bundle(
+            config_path,
+            profile,
+            false,
+            &BundleArgs {
+                sign: args.sign.clone(),
+                ..Default::default()
+            },
+        )?;
+    }
+
+    let verifying_key = args
+        .sign
+        .as_deref()
+        .map(load_signing_key)
+        .transpose()?
+        .map(|signing_key| signing_key.verifying_key());
+
+    let config = config::Config::from_file_with_profile(config_path, profile)?;
+    let output_directory = Path::new(&config.settings.output_directory);
+    let mut config_path_dir_buf = PathBuf::from(config_path);
+    config_path_dir_buf.pop();
+
+    let manifest = Manifest::read(&config.settings.output_directory)?
+        .ok_or_else(|| anyhow::anyhow!("No {} found, run `bundle` first", MANIFEST_FILE_NAME))?;
+
+    let mut entries = vec![read_entry(
+        MANIFEST_FILE_NAME,
+        &output_directory.join(MANIFEST_FILE_NAME),
+    )?];
+    for (bundle, sha256, manifest_signature) in manifest_bundles(&manifest) {
+        let entry = read_entry(bundle, &output_directory.join(bundle))?;
+        if sha256_hex(&entry.content) != sha256 {
+            anyhow::bail!(
+                "{} doesn't match {}, run `bundle` again",
+                bundle,
+                MANIFEST_FILE_NAME
+            );
+        }
+
+        let signature = signature_path(&output_directory.join(bundle));
+        if signature.exists() {
+            let signature_entry = read_entry(&format!("{}.sig", bundle), &signature)?;
+            let signature = String::from_utf8_lossy(&signature_entry.content);
+            // a signature the manifest doesn't know about was left by an earlier build
+            if manifest_signature != Some(signature.trim()) {
+                anyhow::bail!(
+                    "{}.sig wasn't made for this bundle, run `bundle --sign` again",
+                    bundle
+                );
+            }
+            if let Some(verifying_key) = &verifying_key {
+                verify_signature(verifying_key, &entry.content, &signature)
+                    .map_err(|e| anyhow::anyhow!("{}.sig: {}", bundle, e))?;
+            }
+            entries.push(entry);
+            entries.push(signature_entry);
+        } else {
+            entries.push(entry);
+        }
+    }
+
+    // the archive always calls it hashes.lock, the manifest says which profile it's for
+    entries.push(read_entry(
+        HASHES_LOCK_FILE_NAME,
+        &HashesLock::path(&config_path_dir_buf, profile),
+    )?);
+    let versions_lock = config_path_dir_buf.join(VERSIONS_LOCK_FILE_NAME);
+    if versions_lock.exists() {
+        entries.push(read_entry(VERSIONS_LOCK_FILE_NAME, &versions_lock)?);
+    }
+
+    let release = args
+        .release
+        .clone()
+        .or_else(|| read_git_commit().map(|commit| commit.chars().take(12).collect()))
+        .ok_or_else(|| anyhow::anyhow!("Not in a git repository, pass --release <VERSION>"))?;
+    let output = args.output.as_ref().map(PathBuf::from).unwrap_or_else(|| {
+        config_path_dir_buf.join(format!(
+            "opacity-bundles-{}.{}",
+            release,
+            format.extension()
+        ))
+    });
+
+    write_archive(&output, format, &entries, source_date_epoch().unwrap_or(0))?;
+
+    info!(
+        "Packaged {} file(s) into {}",
+        entries.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+/// Lists the files of a release archive and checks its bundles against the manifest and
+/// hashes.lock it contains, and against their signatures if given a public key
+pub fn inspect(archive: &str, public_key: Option<&str>) -> Result<()> {
+    let verifying_key = public_key.map(load_verifying_key).transpose()?;
+    let entries = read_archive(Path::new(archive))?;
+
+    println!("{}:", archive);
+    for entry in &entries {
+        println!("  {:>10}  {}", entry.content.len(), entry.name);
+    }
+    println!();
+
+    let files = entries
+        .iter()
+        .map(|entry| (entry.name.as_str(), entry.content.as_slice()))
+        .collect::<BTreeMap<_, _>>();
+
+    let manifest: Manifest = serde_json::from_slice(
+        files
+            .get(MANIFEST_FILE_NAME)
+            .ok_or_else(|| anyhow::anyhow!("No {} in {}", MANIFEST_FILE_NAME, archive))?,
+    )?;
+    let lock = files
+        .get(HASHES_LOCK_FILE_NAME)
+        .map(|content| HashesLock::parse(&String::from_utf8_lossy(content), Path::new("")))
+        .transpose()?;
+
+    let mut failures = 0;
+    for (bundle, sha256, _) in manifest_bundles(&manifest) {
+        let alias = bundle.strip_suffix(".bundle.luau").unwrap_or(bundle);
+        let result = files
+            .get(bundle)
+            .ok_or_else(|| anyhow::anyhow!("Missing from the archive"))
+            .and_then(|content| {
+                let hash = sha256_hex(content);
+                if hash != sha256 {
+                    anyhow::bail!("SHA-256 does not match {}", MANIFEST_FILE_NAME);
+                }
+                if let Some(lock) = &lock {
+                    match lock.flows.get(alias) {
+                        Some(hashes) if hashes.hash == hash => {}
+                        Some(_) => anyhow::bail!("SHA-256 does not match hashes.lock"),
+                        None => anyhow::bail!("Not found in hashes.lock"),
+                    }
+                }
+                if let Some(verifying_key) = &verifying_key {
+                    let signature = files
+                        .get(format!("{}.sig", bundle).as_str())
+                        .ok_or_else(|| anyhow::anyhow!("No signature in the archive"))?;
+                    verify_signature(verifying_key, content, &String::from_utf8_lossy(signature))?;
+                }
+                Ok(())
+            });
+
+        match result {
+            Ok(()) => println!("OK      {}", bundle),
+            Err(err) => {
+                failures += 1;
+                println!("FAILED  {}: {:#}", bundle, err);
+            }
+        }
+    }
+
+    if failures > 0 {
+        anyhow::bail!("{} bundle(s) failed inspection", failures);
+    }
+
+    info!("All bundles in {} match their hashes", archive);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_archive_format() {
+        let format = |format, output: Option<&str>| archive_format(format, output.map(Path::new));
+
+        assert_eq!(format(None, None).unwrap(), ArchiveFormat::TarGz);
+        assert_eq!(
+            format(Some(ArchiveFormat::Zip), None).unwrap(),
+            ArchiveFormat::Zip
+        );
+        assert_eq!(
+            format(None, Some("release.zip")).unwrap(),
+            ArchiveFormat::Zip
+        );
+        assert_eq!(
+            format(Some(ArchiveFormat::TarGz), Some("release.tgz")).unwrap(),
+            ArchiveFormat::TarGz
+        );
+        assert!(format(Some(ArchiveFormat::Zip), Some("release.tar.gz")).is_err());
+        assert!(format(None, Some("release.tar")).is_err());
+    }
+}
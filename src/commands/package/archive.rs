@@ -0,0 +1,172 @@
+use std::io::{Read, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Timelike};
+use clap::ValueEnum;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ArchiveFormat {
+    #[default]
+    #[value(name = "tar.gz")]
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::Zip => "zip",
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let name = path.to_string_lossy();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Ok(ArchiveFormat::TarGz)
+        } else if name.ends_with(".zip") {
+            Ok(ArchiveFormat::Zip)
+        } else {
+            anyhow::bail!(
+                "Unknown archive format for {}, expected .tar.gz or .zip",
+                name
+            )
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    pub name: String,
+    pub content: Vec<u8>,
+}
+
+/// Writes the entries in name order, with the same timestamp and permissions on every file,
+/// so packaging the same bundles twice gives the same bytes
+pub fn write_archive(
+    path: &Path,
+    format: ArchiveFormat,
+    entries: &[ArchiveEntry],
+    mtime: i64,
+) -> Result<()> {
+    let mut entries = entries.iter().collect::<Vec<_>>();
+    entries.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
+    let file = std::fs::File::create(path)?;
+
+    match format {
+        ArchiveFormat::TarGz => {
+            let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+            for entry in entries {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(entry.content.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(mtime.max(0) as u64);
+                header.set_cksum();
+                builder.append_data(&mut header, &entry.name, entry.content.as_slice())?;
+            }
+            builder.into_inner()?.finish()?;
+        }
+        ArchiveFormat::Zip => {
+            // zip timestamps can't go before 1980
+            let last_modified = DateTime::from_timestamp(mtime, 0)
+                .and_then(|time| {
+                    zip::DateTime::from_date_and_time(
+                        u16::try_from(time.year()).ok()?,
+                        time.month() as u8,
+                        time.day() as u8,
+                        time.hour() as u8,
+                        time.minute() as u8,
+                        time.second() as u8,
+                    )
+                    .ok()
+                })
+                .unwrap_or_default();
+            let options = zip::write::SimpleFileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated)
+                .last_modified_time(last_modified)
+                .unix_permissions(0o644);
+
+            let mut writer = zip::ZipWriter::new(file);
+            for entry in entries {
+                writer.start_file(entry.name.as_str(), options)?;
+                writer.write_all(&entry.content)?;
+            }
+            writer.finish()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Every file of an archive, in archive order
+pub fn read_archive(path: &Path) -> Result<Vec<ArchiveEntry>> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?;
+    let mut entries = Vec::new();
+
+    match ArchiveFormat::from_path(path)? {
+        ArchiveFormat::TarGz => {
+            let mut archive = tar::Archive::new(GzDecoder::new(file));
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                let name = entry.path()?.to_string_lossy().replace('\\', "/");
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content)?;
+                entries.push(ArchiveEntry { name, content });
+            }
+        }
+        ArchiveFormat::Zip => {
+            let mut archive = zip::ZipArchive::new(file)?;
+            for index in 0..archive.len() {
+                let mut entry = archive.by_index(index)?;
+                if entry.is_dir() {
+                    continue;
+                }
+                let name = entry.name().to_string();
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content)?;
+                entries.push(ArchiveEntry { name, content });
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::paths::TestDirectory;
+
+    #[test]
+    fn test_archive_round_trip() {
+        let entries = vec![
+            ArchiveEntry {
+                name: "manifest.json".to_string(),
+                content: b"{}".to_vec(),
+            },
+            ArchiveEntry {
+                name: "login.bundle.luau".to_string(),
+                content: b"return 1".to_vec(),
+            },
+        ];
+        let directory = TestDirectory::create();
+
+        for format in [ArchiveFormat::TarGz, ArchiveFormat::Zip] {
+            let path = directory.join(format!("bundles.{}", format.extension()));
+            write_archive(&path, format, &entries, 0).unwrap();
+            let first = std::fs::read(&path).unwrap();
+
+            let mut reversed = entries.clone();
+            reversed.reverse();
+            write_archive(&path, format, &reversed, 0).unwrap();
+            assert_eq!(std::fs::read(&path).unwrap(), first);
+
+            let read = read_archive(&path).unwrap();
+            assert_eq!(read[0].name, "login.bundle.luau");
+            assert_eq!(read[1], entries[0]);
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use crate::{
-    commands::bundle::{create_options, process_bundle},
+    commands::bundle::{build_flow, collect_flow_modules, create_options, write_source_map},
     config::{Config, Flow, SimplePlatform},
 };
 
@@ -59,7 +59,11 @@ struct SessionResponse {
 }
 
 async fn read_flow(name: &str) -> Result<FlowResponse, String> {
-    let config = crate::config::Config::from_file("./opacity.toml").unwrap();
+    let config = crate::config::Config::from_file_with_profile(
+        "./opacity.toml",
+        PROFILE.get().unwrap().as_deref(),
+    )
+    .unwrap();
 
     let matched_flow = config
         .platforms
@@ -97,7 +101,11 @@ async fn read_flow(name: &str) -> Result<FlowResponse, String> {
 }
 
 async fn rebundle_and_read_flow(name: &str) -> Result<FlowResponse, String> {
-    let config = crate::config::Config::from_file("./opacity.toml").unwrap();
+    let config = crate::config::Config::from_file_with_profile(
+        "./opacity.toml",
+        PROFILE.get().unwrap().as_deref(),
+    )
+    .unwrap();
 
     let (matched_flow, platform_index) = ALIAS_TO_FLOW_MAP_AND_PLATFORM_INDEX
         .get()
@@ -107,10 +115,15 @@ async fn rebundle_and_read_flow(name: &str) -> Result<FlowResponse, String> {
     let flow_platform = &PLATFORM_VECTOR.get().unwrap()[*platform_index];
 
     if *SHOULD_REBUNDLE.get().unwrap() {
-        let bundle_options =
-            create_options(&config, flow_platform, matched_flow).map_err(|e| e.to_string())?;
+        let resources = Resources::from_file_system();
+        let flow_modules = collect_flow_modules(&resources, &config.settings, matched_flow, None)
+            .map_err(|e| e.to_string())?;
+        let bundle_options = create_options(&config, flow_platform, matched_flow, &flow_modules)
+            .map_err(|e| e.to_string())?;
 
-        process_bundle(&Resources::from_file_system(), bundle_options.opts)
+        let obfuscation = bundle_options.obfuscation.clone();
+        let output = build_flow(&resources, bundle_options).map_err(|e| e.to_string())?;
+        write_source_map(&resources, &flow_modules, &output, obfuscation.as_ref())
             .map_err(|e| e.to_string())?;
     }
 
@@ -266,14 +279,19 @@ async fn sessions() -> Json<SessionResponse> {
 }
 
 static SHOULD_REBUNDLE: OnceLock<bool> = OnceLock::new();
+static PROFILE: OnceLock<Option<String>> = OnceLock::new();
 
 pub async fn serve(
     config_path: &str,
+    profile: Option<&str>,
     should_rebundle: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // initialize everything
-    get_alias_to_flow_map_and_platform_index(&Config::from_file(config_path).unwrap());
+    get_alias_to_flow_map_and_platform_index(
+        &Config::from_file_with_profile(config_path, profile).unwrap(),
+    );
     SHOULD_REBUNDLE.get_or_init(|| should_rebundle);
+    PROFILE.get_or_init(|| profile.map(str::to_string));
 
     let port = 8080;
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
@@ -0,0 +1,227 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use darklua_core::Resources;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::aliases::configured_aliases;
+use crate::commands::bundle::size_report::format_size;
+use crate::commands::bundle::source_map::SourceMap;
+use crate::commands::bundle::{display_path, get_obfuscation, sha256_hex, source_map_path};
+use crate::commands::version::compute_version_for_flows;
+use crate::config;
+
+const BUNDLE_MODULES_PREFIX: &str = "__BUNDLE_MODULES.";
+
+/// How a module is embedded across the bundles
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SharedModule {
+    pub module: String,
+    /// Aliases of the flows whose bundle embeds the module
+    pub flows: Vec<String>,
+    /// Bytes the module accounts for, summed over every bundle
+    pub total_bytes: u64,
+    /// Flows grouped by identical embedded code, more than one group means the copies differ
+    pub copies: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SharedModulesReport {
+    pub modules: Vec<SharedModule>,
+    /// Bytes that would go away if every copy of a module was shipped only once
+    pub duplicated_bytes: u64,
+    /// Flows whose modules aren't sized nor compared: the obfuscation renames locals and
+    /// encodes strings differently in each bundle, so no two copies would ever match
+    pub obfuscated_flows: Vec<String>,
+}
+
+/// The bundle names its modules `__BUNDLE_MODULES.<name>` in the order it embeds them, so the
+/// same module can get a different name in each bundle
+fn normalize_module_names(code: &str) -> String {
+    let mut normalized = String::with_capacity(code.len());
+    let mut rest = code;
+    while let Some(index) = rest.find(BUNDLE_MODULES_PREFIX) {
+        let end = index + BUNDLE_MODULES_PREFIX.len();
+        normalized.push_str(&rest[..end]);
+        normalized.push('_');
+        rest = rest[end..].trim_start_matches(|char: char| char.is_alphanumeric() || char == '_');
+    }
+    normalized.push_str(rest);
+    normalized
+}
+
+/// Reads a bundle and its source map, and returns the hash and size of each module's code
+fn embedded_modules(output: &Path) -> Result<BTreeMap<String, (String, u64)>> {
+    let generated = std::fs::read_to_string(output)?;
+    let source_map: SourceMap =
+        serde_json::from_str(&std::fs::read_to_string(source_map_path(output))?)?;
+
+    Ok(source_map
+        .generated_code_per_source(&generated)?
+        .into_iter()
+        .filter_map(|(source, code)| {
+            let hash = sha256_hex(normalize_module_names(&code).as_bytes());
+            Some((source?, (hash, code.len() as u64)))
+        })
+        .collect())
+}
+
+pub fn shared_modules_report(
+    config: &config::Config,
+    resources: &Resources,
+) -> Result<SharedModulesReport> {
+    let flows = config
+        .platforms
+        .iter()
+        .flat_map(|platform| &platform.flows)
+        .collect::<Vec<_>>();
+    let work = compute_version_for_flows(
+        resources,
+        flows.iter().map(|flow| PathBuf::from(&flow.path)).collect(),
+        Default::default(),
+        &configured_aliases(&config.settings),
+    )?;
+
+    let mut modules: BTreeMap<String, SharedModule> = BTreeMap::new();
+    let mut copies: BTreeMap<String, BTreeMap<String, Vec<String>>> = BTreeMap::new();
+    let mut copy_sizes: BTreeMap<(String, String), u64> = BTreeMap::new();
+    let mut obfuscated_flows = Vec::new();
+
+    for flow in flows {
+        let output = Path::new(&config.settings.output_directory)
+            .join(format!("{}.bundle.luau", flow.alias));
+        let embedded = match embedded_modules(&output) {
+            Ok(_) if get_obfuscation(&config.settings, flow).is_some() => {
+                obfuscated_flows.push(flow.alias.clone());
+                BTreeMap::new()
+            }
+            Ok(embedded) => embedded,
+            Err(err) => {
+                warn!(
+                    "No sizes for {}, bundle it first ({}: {})",
+                    flow.alias,
+                    output.display(),
+                    err
+                );
+                BTreeMap::new()
+            }
+        };
+
+        let closure = work.module_closure(Path::new(&flow.path));
+        for module in closure.iter().map(|module| display_path(module)) {
+            let shared_module = modules.entry(module.clone()).or_default();
+            shared_module.flows.push(flow.alias.clone());
+
+            if let Some((hash, size)) = embedded.get(&module) {
+                shared_module.total_bytes += size;
+                copies
+                    .entry(module.clone())
+                    .or_default()
+                    .entry(hash.clone())
+                    .or_default()
+                    .push(flow.alias.clone());
+                copy_sizes.insert((module, hash.clone()), *size);
+            }
+        }
+    }
+
+    let mut duplicated_bytes = 0;
+    let mut modules = modules
+        .into_iter()
+        .map(|(module, mut shared_module)| {
+            let module_copies = copies.remove(&module).unwrap_or_default();
+            let shipped_once = module_copies
+                .keys()
+                .map(|hash| copy_sizes[&(module.clone(), hash.clone())])
+                .sum::<u64>();
+            duplicated_bytes += shared_module.total_bytes.saturating_sub(shipped_once);
+
+            shared_module.module = module;
+            shared_module.copies = module_copies.into_values().collect();
+            shared_module
+        })
+        .collect::<Vec<_>>();
+    modules.sort_by(|lhs, rhs| {
+        rhs.total_bytes
+            .cmp(&lhs.total_bytes)
+            .then_with(|| lhs.module.cmp(&rhs.module))
+    });
+
+    Ok(SharedModulesReport {
+        modules,
+        duplicated_bytes,
+        obfuscated_flows,
+    })
+}
+
+/// Lists every module embedded by more than one flow, how much it weighs across all the
+/// bundles, and whether its copies differ
+pub fn shared_modules(config_path: &str, profile: Option<&str>, json: bool) -> Result<()> {
+    let config = config::Config::from_file_with_profile(config_path, profile)?;
+    let mut report = shared_modules_report(&config, &Resources::from_file_system())?;
+    report.modules.retain(|module| module.flows.len() > 1);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    println!("{:>10}  {:>5}  Module", "Total", "Flows");
+    for module in &report.modules {
+        println!(
+            "{:>10}  {:>5}  {}",
+            format_size(module.total_bytes),
+            module.flows.len(),
+            module.module
+        );
+        if module.copies.len() > 1 {
+            println!("{:>19}{} different copies:", "", module.copies.len());
+            for flows in &module.copies {
+                println!("{:>21}{}", "", flows.join(", "));
+            }
+        }
+    }
+
+    let differing = report
+        .modules
+        .iter()
+        .filter(|module| module.copies.len() > 1)
+        .count();
+    println!();
+    println!(
+        "{} shared module(s), {} duplicated across bundles, {} with differing copies",
+        report.modules.len(),
+        format_size(report.duplicated_bytes),
+        differing
+    );
+    if !report.obfuscated_flows.is_empty() {
+        println!(
+            "Not compared, their bundles are obfuscated: {}",
+            report.obfuscated_flows.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize_module_names() {
+        assert_eq!(
+            normalize_module_names("function __BUNDLE_MODULES.b()\nreturn __modImpl()end"),
+            "function __BUNDLE_MODULES._()\nreturn __modImpl()end"
+        );
+        assert_eq!(
+            normalize_module_names("local a=__BUNDLE_MODULES.ab_2()"),
+            normalize_module_names("local a=__BUNDLE_MODULES.c()")
+        );
+        assert_eq!(normalize_module_names("return 1"), "return 1");
+    }
+}
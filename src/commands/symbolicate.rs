@@ -0,0 +1,251 @@
+use crate::commands::bundle::{bundle_aliases, source_map::SourceMap, source_map_path};
+use crate::config;
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Extracts the flow alias from a chunk name such as `[string "alias"]`,
+/// `out/alias.bundle.luau` or just `alias`
+fn chunk_alias(chunk: &str) -> &str {
+    let chunk = chunk
+        .strip_prefix("[string \"")
+        .and_then(|chunk| chunk.strip_suffix("\"]"))
+        .unwrap_or(chunk);
+    let file_name = chunk.rsplit(['/', '\\']).next().unwrap_or(chunk);
+    file_name
+        .strip_suffix(".bundle.luau")
+        .or_else(|| file_name.strip_suffix(".luau"))
+        .unwrap_or(file_name)
+}
+
+/// Whether a chunk name is one a bundle can be loaded under, `[string "..."]` or a Luau file,
+/// so `--flow` leaves `12:34:56` or `host:8080` alone
+fn is_bundle_chunk(chunk: &str) -> bool {
+    (chunk.starts_with("[string \"") && chunk.ends_with("\"]"))
+        || chunk.ends_with(".luau")
+        || chunk.ends_with(".lua")
+}
+
+/// Finds where the chunk name ending right before `end` starts
+fn chunk_start(line: &str, end: usize) -> usize {
+    let before = &line[..end];
+    if before.ends_with("\"]") {
+        if let Some(start) = before.rfind("[string \"") {
+            return start;
+        }
+    }
+    before
+        .char_indices()
+        .rev()
+        .find(|(_, c)| c.is_whitespace() || matches!(c, '(' | ')' | '\'' | '"' | ','))
+        .map(|(start, c)| start + c.len_utf8())
+        .unwrap_or(0)
+}
+
+struct Symbolicator {
+    output_directory: PathBuf,
+    aliases: Vec<String>,
+    default_alias: Option<String>,
+    source_maps: HashMap<String, Option<SourceMap>>,
+}
+
+impl Symbolicator {
+    fn source_map(&mut self, alias: &str) -> Result<Option<&SourceMap>> {
+        if !self.source_maps.contains_key(alias) {
+            let path =
+                source_map_path(&self.output_directory.join(format!("{}.bundle.luau", alias)));
+            let source_map =
+                match std::fs::read_to_string(&path) {
+                    Ok(content) => Some(serde_json::from_str(&content).with_context(|| {
+                        format!("Failed to parse source map {}", path.display())
+                    })?),
+                    Err(_) => None,
+                };
+            self.source_maps.insert(alias.to_string(), source_map);
+        }
+        Ok(self.source_maps.get(alias).unwrap().as_ref())
+    }
+
+    /// Rewrites every `<chunk>:<line>` reference to a bundle into `<source>:<line>`
+    fn symbolicate_line(&mut self, line: &str) -> Result<String> {
+        let mut result = String::with_capacity(line.len());
+        let mut copied = 0;
+        let mut search_from = 0;
+
+        while let Some(offset) = line[search_from..].find(':') {
+            let colon = search_from + offset;
+            search_from = colon + 1;
+
+            let digits = line[colon + 1..]
+                .find(|c: char| !c.is_ascii_digit())
+                .map(|length| colon + 1 + length)
+                .unwrap_or(line.len());
+            if digits == colon + 1 {
+                continue;
+            }
+
+            let start = chunk_start(line, colon).max(copied);
+            let chunk = &line[start..colon];
+            let alias = chunk_alias(chunk);
+            let alias = if self.aliases.iter().any(|known| known == alias) {
+                alias.to_string()
+            } else {
+                match &self.default_alias {
+                    Some(default_alias) if is_bundle_chunk(chunk) => default_alias.clone(),
+                    _ => continue,
+                }
+            };
+
+            let Ok(generated_line) = line[colon + 1..digits].parse::<u32>() else {
+                continue;
+            };
+            let location = match self.source_map(&alias)? {
+                Some(source_map) => source_map.lookup(generated_line, None)?,
+                None => None,
+            };
+
+            if let Some(location) = location {
+                result.push_str(&line[copied..start]);
+                result.push_str(&format!("{}:{}", location.source, location.line));
+                copied = digits;
+                search_from = digits;
+            }
+        }
+
+        result.push_str(&line[copied..]);
+        Ok(result)
+    }
+}
+
+pub fn symbolicate(
+    config_path: &str,
+    profile: Option<&str>,
+    flow: Option<&str>,
+    input: Option<&str>,
+) -> Result<()> {
+    let config = config::Config::from_file_with_profile(config_path, profile)?;
+
+    let text = match input {
+        Some(input) => std::fs::read_to_string(input)
+            .with_context(|| format!("Failed to read stack trace from {}", input))?,
+        None => {
+            let mut text = String::new();
+            std::io::stdin().read_to_string(&mut text)?;
+            text
+        }
+    };
+
+    // variants have their own source maps, `login@26-` must not be read with `login`'s
+    let aliases: Vec<String> = config
+        .platforms
+        .iter()
+        .flat_map(|platform| platform.flows.iter())
+        .flat_map(bundle_aliases)
+        .collect();
+
+    if let Some(flow) = flow {
+        if !aliases.iter().any(|alias| alias == flow) {
+            anyhow::bail!("Flow not found: {}", flow);
+        }
+    }
+
+    let mut symbolicator = Symbolicator {
+        output_directory: PathBuf::from(&config.settings.output_directory),
+        aliases,
+        default_alias: flow.map(str::to_string),
+        source_maps: HashMap::new(),
+    };
+
+    for line in text.lines() {
+        println!("{}", symbolicator.symbolicate_line(line)?);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_symbolicate_line_with_default_flow() {
+        let main = "local helper = require(\"./helper\")\n\nlocal value = helper.compute(1)\nprint(value)\n";
+        let helper = "local helper = {}\n\nfunction helper.compute(x)\n    return x * 2\nend\n\nreturn helper\n";
+        let generated = "local __BUNDLE_MODULES={cache={}}do do local function __modImpl()local helper={}\nfunction helper.compute(x)return x*2 end return helper end function __BUNDLE_MODULES.a()\nreturn __modImpl()end end end local helper=__BUNDLE_MODULES.a()local value=\nhelper.compute(1)print(value)";
+        let source_map = SourceMap::generate(
+            "main.bundle.luau",
+            generated,
+            &[
+                ("main.luau".to_string(), main.to_string()),
+                ("helper.luau".to_string(), helper.to_string()),
+            ],
+            None,
+        );
+
+        let mut symbolicator = Symbolicator {
+            output_directory: PathBuf::new(),
+            aliases: vec!["main".to_string()],
+            default_alias: Some("main".to_string()),
+            source_maps: HashMap::from([("main".to_string(), Some(source_map))]),
+        };
+
+        assert_eq!(
+            symbolicator
+                .symbolicate_line("12:34:56 [string \"loader\"]:2: failed to reach host:8080")
+                .unwrap(),
+            "12:34:56 helper.luau:3: failed to reach host:8080"
+        );
+        assert_eq!(
+            symbolicator
+                .symbolicate_line("[string \"loader\"]:99999999999: overflow")
+                .unwrap(),
+            "[string \"loader\"]:99999999999: overflow"
+        );
+        assert_eq!(
+            symbolicator.symbolicate_line("main:4: error").unwrap(),
+            "main.luau:3: error"
+        );
+    }
+
+    #[test]
+    fn test_symbolicate_line_with_variant() {
+        let main = "local helper = require(\"./helper\")\n\nlocal value = helper.compute(1)\nprint(value)\n";
+        let generated = "local __BUNDLE_MODULES={cache={}}local helper=__BUNDLE_MODULES.a()local value=\nhelper.compute(1)print(value)";
+        let source_map = SourceMap::generate(
+            "main.bundle.luau",
+            generated,
+            &[("main.luau".to_string(), main.to_string())],
+            None,
+        );
+        // the variant keeps the layout of the source, each line maps to itself
+        let variant_source_map = SourceMap::generate(
+            "main@26-.bundle.luau",
+            main,
+            &[("main.luau".to_string(), main.to_string())],
+            None,
+        );
+
+        let mut symbolicator = Symbolicator {
+            output_directory: PathBuf::new(),
+            aliases: vec!["main".to_string(), "main@26-".to_string()],
+            default_alias: Some("main".to_string()),
+            source_maps: HashMap::from([
+                ("main".to_string(), Some(source_map)),
+                ("main@26-".to_string(), Some(variant_source_map)),
+            ]),
+        };
+
+        assert_eq!(
+            symbolicator
+                .symbolicate_line("out/main@26-.bundle.luau:4: error")
+                .unwrap(),
+            "main.luau:4: error"
+        );
+        assert_eq!(
+            symbolicator.symbolicate_line("main:2: error").unwrap(),
+            "main.luau:3: error"
+        );
+    }
+}
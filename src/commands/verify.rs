@@ -0,0 +1,102 @@
+use crate::commands::bundle::lock::HashesLock;
+use crate::commands::bundle::{sha256_hex, variant_alias};
+use crate::commands::keys::{load_verifying_key, signature_path, verify_signature};
+use crate::config;
+
+use anyhow::{Context, Result};
+use ed25519_dalek::VerifyingKey;
+use std::path::{Path, PathBuf};
+
+fn verify_bundle(verifying_key: &VerifyingKey, bundle: &Path, expected_hash: &str) -> Result<()> {
+    let content =
+        std::fs::read(bundle).with_context(|| format!("Failed to read {}", bundle.display()))?;
+
+    if sha256_hex(&content) != expected_hash {
+        anyhow::bail!("SHA-256 does not match hashes.lock");
+    }
+
+    let signature = std::fs::read_to_string(signature_path(bundle))
+        .with_context(|| format!("No signature found for {}", bundle.display()))?;
+    verify_signature(verifying_key, &content, &signature)
+}
+
+/// Checks every bundle against its signature and the SHA-256 recorded in hashes.lock
+pub fn verify(config_path: &str, profile: Option<&str>, public_key: &str) -> Result<()> {
+    let config = config::Config::from_file_with_profile(config_path, profile)?;
+    let verifying_key = load_verifying_key(public_key)?;
+
+    let mut config_path_dir_buf = PathBuf::from(config_path);
+    config_path_dir_buf.pop();
+    let lock = HashesLock::read(&config_path_dir_buf, profile)?
+        .ok_or_else(|| anyhow::anyhow!("No hashes.lock found, run `bundle --sign` first"))?;
+
+    let mut failures = 0;
+    for platform in &config.platforms {
+        for flow in &platform.flows {
+            let aliases = std::iter::once(flow.alias.clone()).chain(
+                flow.variants
+                    .iter()
+                    .flatten()
+                    .map(|variant| variant_alias(&flow.alias, variant)),
+            );
+
+            for alias in aliases {
+                let result = match lock.flows.get(&alias) {
+                    Some(hashes) => verify_bundle(
+                        &verifying_key,
+                        &config_path_dir_buf.join(&hashes.bundle),
+                        &hashes.hash,
+                    ),
+                    None => Err(anyhow::anyhow!("Not found in hashes.lock")),
+                };
+
+                match result {
+                    Ok(()) => println!("OK      {} ({})", flow.name, alias),
+                    Err(err) => {
+                        failures += 1;
+                        println!("FAILED  {} ({}): {:#}", flow.name, alias, err);
+                    }
+                }
+            }
+        }
+    }
+
+    if failures > 0 {
+        anyhow::bail!("{} bundle(s) failed verification", failures);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::keys::sign;
+    use crate::paths::TestDirectory;
+    use ed25519_dalek::SigningKey;
+
+    #[test]
+    fn test_verify_bundle() {
+        let directory = TestDirectory::create();
+        let bundle = directory.join("example.bundle.luau");
+        std::fs::write(&bundle, "print(1)").unwrap();
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let verifying_key = signing_key.verifying_key();
+        let hash = sha256_hex(b"print(1)");
+
+        let missing_signature = verify_bundle(&verifying_key, &bundle, &hash);
+        std::fs::write(signature_path(&bundle), sign(&signing_key, b"print(1)")).unwrap();
+        let signed = verify_bundle(&verifying_key, &bundle, &hash);
+        let wrong_hash = verify_bundle(&verifying_key, &bundle, &sha256_hex(b"print(2)"));
+
+        assert!(missing_signature
+            .unwrap_err()
+            .to_string()
+            .starts_with("No signature found"));
+        assert!(signed.is_ok());
+        assert_eq!(
+            wrong_hash.unwrap_err().to_string(),
+            "SHA-256 does not match hashes.lock"
+        );
+    }
+}
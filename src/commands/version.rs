@@ -4,6 +4,7 @@ use anyhow::Result;
 use darklua_core::Resources;
 
 use crate::{
+    aliases::{configured_aliases, Aliases},
     commands::version::{
         dependency_graph::{DepedencyGraph, Work},
         version_visitor::VersionFile,
@@ -12,9 +13,10 @@ use crate::{
 };
 
 pub mod dependency_graph;
-mod dependency_visitor;
+pub mod dependency_visitor;
 mod has_call_to_function_visitor;
 pub mod sdk_version;
+pub mod sdk_version_folder;
 mod utils;
 pub mod version_visitor;
 
@@ -22,15 +24,33 @@ pub fn compute_version_for_flows<'a>(
     resources: &Resources,
     flow_paths: Vec<PathBuf>,
     version_file: VersionFile,
+    aliases: &Aliases,
 ) -> Result<Work<'_>> {
     let graph = DepedencyGraph::new();
-    let mut work = Work::new(graph, resources, flow_paths, version_file);
+    let mut work =
+        Work::new(graph, resources, flow_paths, version_file).with_aliases(aliases.clone());
     work.compute_dependency_graph()
         .map_err(|e| anyhow::anyhow!("Failed to compute dependency graph: {:?}", e))?;
 
     Ok(work)
 }
 
+/// The version file lives next to the config: `version_file.json`
+pub fn version_file_path(config_path: &str) -> PathBuf {
+    let mut config_path_dir_buf = PathBuf::from(config_path);
+    config_path_dir_buf.pop();
+    config_path_dir_buf.join("version_file.json")
+}
+
+pub fn read_version_file(config_path: &str) -> Result<VersionFile> {
+    let version_file: VersionFile = serde_json::from_str(
+        &std::fs::read_to_string(version_file_path(config_path)).map_err(|e| {
+            anyhow::anyhow!("Failed to read version file (version_file.json): {:?}", e)
+        })?,
+    )?;
+    Ok(version_file)
+}
+
 pub fn compute_versions(config_path: &str) -> Result<()> {
     let config = config::Config::from_file(config_path)?;
     let resources = Resources::from_file_system();
@@ -47,15 +67,14 @@ pub fn compute_versions(config_path: &str) -> Result<()> {
         }
     }
 
-    let mut config_path_dir_buf = PathBuf::from(config_path);
-    config_path_dir_buf.pop();
-    let version_file: VersionFile = serde_json::from_str(
-        &std::fs::read_to_string(config_path_dir_buf.join("version_file.json")).map_err(|e| {
-            anyhow::anyhow!("Failed to read version file (version_file.json): {:?}", e)
-        })?,
-    )?;
+    let version_file = read_version_file(config_path)?;
 
-    let work = compute_version_for_flows(&resources, file_paths, version_file)?;
+    let work = compute_version_for_flows(
+        &resources,
+        file_paths,
+        version_file,
+        &configured_aliases(&config.settings),
+    )?;
 
     let versions = work.get_versions();
 
@@ -29,18 +29,30 @@ use std::{
 
 use darklua_core::{
     process::{DefaultVisitor, NodeVisitor, ScopeVisitor},
-    rules::{ContextBuilder, PathRequireMode, RequirePathLocator},
+    rules::{ContextBuilder, RequirePathLocator},
     Configuration, Resources,
 };
 use petgraph::algo::toposort;
 use tracing::warn;
 
+use crate::aliases::{aliases_for, path_require_mode, Aliases};
 use crate::commands::version::{
     dependency_visitor::RequireDependencyProcessor,
     sdk_version::SdkVersionOut,
     utils::normalize_path,
     version_visitor::{VersionFile, VersionResolver},
 };
+use crate::diagnostics::Diagnostic;
+
+/// Data files darklua turns into Lua values when they are required
+const DATA_MODULE_EXTENSIONS: [&str; 6] = ["json", "json5", "yaml", "yml", "toml", "txt"];
+
+/// Whether a required file is data (`require("./selectors.json")`) rather than Luau code
+pub fn is_data_module(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| DATA_MODULE_EXTENSIONS.contains(&extension))
+}
 
 #[derive(Default, Debug, Clone)]
 pub enum State {
@@ -96,6 +108,8 @@ pub struct Work<'a> {
     configuration: Configuration,
     top_node_paths: Vec<PathBuf>,
     version_file: VersionFile,
+    /// Aliases of opacity.toml, the `.luaurc` ones are read for each module
+    aliases: Aliases,
 }
 
 impl<'a> Work<'a> {
@@ -112,9 +126,15 @@ impl<'a> Work<'a> {
             top_node_paths,
             configuration: Configuration::default(),
             version_file,
+            aliases: Aliases::new(),
         }
     }
 
+    pub fn with_aliases(mut self, aliases: Aliases) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
     /// Given a Vec<PathBuf>, create nodes for each dependency and add them to the graph
     /// Also, add everything to the node_mapping
     ///
@@ -152,34 +172,39 @@ impl<'a> Work<'a> {
         };
 
         match state {
+            State::NotProcessed if is_data_module(&self.get_node(node_index).path) => {
+                // data files are leaves, they don't require anything nor use SDK functions
+                self.get_node_mut(node_index).state = State::Processed;
+                Ok(State::Processed)
+            }
             State::NotProcessed => {
                 // traverse and collect all the deps
 
-                // we don't care about the parser retaining lines or being dense, just go with the default one
-                let parser = darklua_core::Parser::default();
-
-                let mut block = parser
-                    .parse(
-                        self.resources
-                            .get(&self.get_node(node_index).path)
-                            .map_err(|e| {
-                                anyhow::anyhow!(
-                                    "Failed to read file {}: {:?}",
-                                    self.get_node(node_index).path.display(),
-                                    e
-                                )
-                            })?
-                            .as_str(),
-                    )
+                // tokens give the lines of the requires that can't be resolved
+                let parser = darklua_core::Parser::default().preserve_tokens();
+
+                let source = self
+                    .resources
+                    .get(&self.get_node(node_index).path)
                     .map_err(|e| {
                         anyhow::anyhow!(
-                            "Failed to parse file {}: {:?}",
+                            "Failed to read file {}: {:?}",
                             self.get_node(node_index).path.display(),
                             e
                         )
                     })?;
-
-                let deps = self.collect_dependencies(node_index, &mut block)?.clone();
+                let mut block = parser.parse(&source).map_err(|e| {
+                    let path = &self.get_node(node_index).path;
+                    Diagnostic::error("parse", e.to_string())
+                        .with_location_from_message()
+                        .with_file(path)
+                        .emit();
+                    anyhow::anyhow!("Failed to parse file {}", path.display())
+                })?;
+
+                let deps = self
+                    .collect_dependencies(node_index, &mut block, &source)?
+                    .clone();
 
                 self.add_dependencies_to_graph(deps.clone());
 
@@ -246,12 +271,15 @@ impl<'a> Work<'a> {
         &mut self,
         node_index: petgraph::stable_graph::NodeIndex,
         block: &mut darklua_core::nodes::Block,
+        source: &str,
     ) -> anyhow::Result<Vec<PathBuf>> {
         // HARDCODED
         let context = self
             .create_rule_context(&self.graph.node_weight(node_index).unwrap().path, "")
             .build();
-        let mut path_require_mode = PathRequireMode::default();
+        let module_path = &self.get_node(node_index).path;
+        let aliases = aliases_for(module_path.parent().unwrap_or(Path::new("")), &self.aliases)?;
+        let mut path_require_mode = path_require_mode(&aliases)?;
         path_require_mode
             .initialize(&context)
             .map_err(|e| anyhow::anyhow!("Failed to initialize path require mode: {:?}", e))?;
@@ -264,15 +292,19 @@ impl<'a> Work<'a> {
 
         let mut visitor = RequireDependencyProcessor::new(
             self.get_node(node_index).path.clone(),
+            source,
             require_path_locator,
         );
 
         DefaultVisitor::visit_block(block, &mut visitor);
 
-        if visitor.errors().len() > 0 {
+        if !visitor.errors().is_empty() {
+            for diagnostic in visitor.errors() {
+                diagnostic.emit();
+            }
             return Err(anyhow::anyhow!(
-                "Failed to collect dependencies: {:?}",
-                visitor.errors()
+                "Failed to collect dependencies of {}",
+                module_path.display()
             ));
         }
 
@@ -397,6 +429,25 @@ impl<'a> Work<'a> {
             .collect()
     }
 
+    /// Every module a top node pulls in (itself included), in depth-first require order
+    pub fn module_closure(&self, top_node_path: &Path) -> Vec<PathBuf> {
+        let mut closure = Vec::new();
+        let mut stack = vec![normalize_path(top_node_path)];
+
+        while let Some(path) = stack.pop() {
+            if closure.contains(&path) {
+                continue;
+            }
+            if let Some(node_index) = self.node_mapping.get(&path) {
+                // push in reverse so that the first require is visited first
+                stack.extend(self.get_node(*node_index).depends_on.iter().rev().cloned());
+            }
+            closure.push(path);
+        }
+
+        closure
+    }
+
     #[allow(dead_code)]
     pub fn dot_graph(&self) -> String {
         use petgraph::dot::{Config, Dot};
@@ -419,3 +470,45 @@ impl<'a> Work<'a> {
         dot_string
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::version::compute_version_for_flows;
+
+    #[test]
+    fn test_data_modules_are_leaves() {
+        let resources = Resources::from_memory();
+        for (path, content) in [
+            (
+                "src/flow.luau",
+                "local selectors = require(\"./selectors.yaml\")\n\
+                 local limits = require(\"./limits.json5\")\n\
+                 return { selectors, limits }\n",
+            ),
+            // not Luau, the graph must not try to parse them
+            ("src/selectors.yaml", "login: \"#login\"\n"),
+            ("src/limits.json5", "{ retries: 3, }\n"),
+        ] {
+            resources.write(path, content).unwrap();
+        }
+
+        let work = compute_version_for_flows(
+            &resources,
+            vec![PathBuf::from("src/flow.luau")],
+            VersionFile::default(),
+            &Aliases::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            work.module_closure(Path::new("src/flow.luau")),
+            vec![
+                PathBuf::from("src/flow.luau"),
+                PathBuf::from("src/selectors.yaml"),
+                PathBuf::from("src/limits.json5"),
+            ]
+        );
+        assert!(is_data_module(Path::new("src/selectors.yml")));
+        assert!(!is_data_module(Path::new("src/flow.luau")));
+    }
+}
@@ -32,20 +32,23 @@ use darklua_core::{
 };
 
 use crate::commands::version::utils::normalize_path_with_current_dir;
+use crate::diagnostics::Diagnostic;
 
 #[derive(Debug)]
 pub struct RequireDependencyProcessor<'a, 'b, 'c> {
     depends_on: Vec<PathBuf>,
     current_file_path: PathBuf,
+    /// Lines of the current file, to locate the requires that can't be resolved
+    lines: Vec<String>,
     require_path_locator: RequirePathLocator<'a, 'b, 'c>,
-    errors: Vec<anyhow::Error>,
+    errors: Vec<Diagnostic>,
 }
 
 const REQUIRE_FUNCTION_IDENTIFIER: &str = "require";
 
 /// This doesn't use an IdentifierTracker like the one from the DarkLua Project
 /// As we assume people ONLY use the REQUIRE identifier directly to require files
-fn is_require_call(call: &nodes::FunctionCall) -> bool {
+pub fn is_require_call(call: &nodes::FunctionCall) -> bool {
     if call.get_method().is_some() {
         return false;
     }
@@ -56,6 +59,43 @@ fn is_require_call(call: &nodes::FunctionCall) -> bool {
     }
 }
 
+/// The literal a require call is made with, as written
+pub fn require_literal(call: &nodes::FunctionCall) -> Option<&str> {
+    match call.get_arguments() {
+        nodes::Arguments::String(string) => string.get_string_value(),
+        nodes::Arguments::Tuple(tuple) => match tuple.iter_values().next() {
+            Some(nodes::Expression::String(string)) => string.get_string_value(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// 1-based column of the `require` call of `literal` in `line`
+fn require_column(line: &str, literal: &str) -> Option<usize> {
+    let literal_start = line.find(literal)?;
+    line[..literal_start]
+        .rfind(REQUIRE_FUNCTION_IDENTIFIER)
+        .map(|start| line[..start].chars().count() + 1)
+}
+
+/// Line of a require call from its `require` token, the block being parsed with its tokens,
+/// and the column of `require` on that line of `lines`
+fn require_location(
+    call: &nodes::FunctionCall,
+    lines: &[String],
+) -> Option<(usize, Option<usize>)> {
+    let nodes::Prefix::Identifier(identifier) = call.get_prefix() else {
+        return None;
+    };
+    let line = identifier.get_token()?.get_line_number()?;
+    let column = lines
+        .get(line.checked_sub(1)?)
+        .zip(require_literal(call))
+        .and_then(|(text, literal)| require_column(text, literal));
+    Some((line, column))
+}
+
 fn convert_string_expression_to_path(string: &nodes::StringExpression) -> Option<&Path> {
     string
         .get_string_value()
@@ -82,11 +122,13 @@ pub fn match_path_require_call(call: &nodes::FunctionCall) -> Option<PathBuf> {
 impl<'a, 'b, 'c> RequireDependencyProcessor<'a, 'b, 'c> {
     pub fn new(
         current_file_path: PathBuf,
+        source: &str,
         require_path_locator: RequirePathLocator<'a, 'b, 'c>,
     ) -> Self {
         Self {
             depends_on: Vec::new(),
             current_file_path,
+            lines: source.lines().map(str::to_string).collect(),
             require_path_locator,
             errors: Vec::new(),
         }
@@ -103,12 +145,23 @@ impl<'a, 'b, 'c> RequireDependencyProcessor<'a, 'b, 'c> {
 
         let require_path = match self
             .require_path_locator
-            .find_require_path(literal_require_path, &self.current_file_path)
+            .find_require_path(literal_require_path.clone(), &self.current_file_path)
         {
             Ok(path) => path,
             Err(err) => {
-                self.errors
-                    .push(anyhow::anyhow!("Failed to find require path: {:?}", err));
+                let diagnostic = Diagnostic::error(
+                    "require",
+                    format!(
+                        "unable to resolve require `{}`: {}",
+                        literal_require_path.display(),
+                        err
+                    ),
+                )
+                .with_file(&self.current_file_path);
+                self.errors.push(match require_location(call, &self.lines) {
+                    Some((line, column)) => diagnostic.with_location(line, column),
+                    None => diagnostic,
+                });
                 return None;
             }
         };
@@ -119,7 +172,7 @@ impl<'a, 'b, 'c> RequireDependencyProcessor<'a, 'b, 'c> {
     pub fn deps(&self) -> &Vec<PathBuf> {
         &self.depends_on
     }
-    pub fn errors(&self) -> &Vec<anyhow::Error> {
+    pub fn errors(&self) -> &Vec<Diagnostic> {
         &self.errors
     }
 }
@@ -143,3 +196,26 @@ impl<'a, 'b, 'c> NodeProcessor for RequireDependencyProcessor<'a, 'b, 'c> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_require_location() {
+        let source = "local a = 1\nlocal b, c = a, require(\"./missing\")\n";
+        let block = darklua_core::Parser::default()
+            .preserve_tokens()
+            .parse(source)
+            .unwrap();
+        let Some(nodes::Statement::LocalAssign(assign)) = block.iter_statements().nth(1) else {
+            panic!("expected a local assignment");
+        };
+        let Some(nodes::Expression::Call(call)) = assign.iter_values().nth(1) else {
+            panic!("expected a require call");
+        };
+
+        let lines = source.lines().map(str::to_string).collect::<Vec<_>>();
+        assert_eq!(require_location(call, &lines), Some((2, Some(17))));
+    }
+}
@@ -0,0 +1,221 @@
+use darklua_core::nodes::{self, BinaryOperator, Expression};
+use darklua_core::process::{NodeProcessor, NodeVisitor, Scope, ScopeVisitor};
+use darklua_core::rules::{
+    Context, FlawlessRule, RuleConfiguration, RuleConfigurationError, RuleProperties,
+};
+use darklua_core::ScopedHashMap;
+
+use crate::commands::version::utils::get_fqn;
+use crate::config::SdkVariant;
+
+/// Value of a comparison between the SDK version and `value` over the whole SDK range of
+/// `variant`, or `None` if it depends on the actual SDK version
+pub fn fold_comparison(
+    operator: BinaryOperator,
+    sdk_version_on_left: bool,
+    value: f64,
+    variant: &SdkVariant,
+) -> Option<bool> {
+    let min = variant.min.unwrap_or(0) as f64;
+    let max = variant.max.map_or(f64::INFINITY, |max| max as f64);
+
+    match operator {
+        BinaryOperator::Equal => {
+            if min == max && min == value {
+                Some(true)
+            } else if value < min || value > max {
+                Some(false)
+            } else {
+                None
+            }
+        }
+        BinaryOperator::NotEqual => {
+            fold_comparison(BinaryOperator::Equal, sdk_version_on_left, value, variant)
+                .map(|equal| !equal)
+        }
+        _ => {
+            let compare = |sdk_version: f64| {
+                let (left, right) = if sdk_version_on_left {
+                    (sdk_version, value)
+                } else {
+                    (value, sdk_version)
+                };
+                match operator {
+                    BinaryOperator::LowerThan => Some(left < right),
+                    BinaryOperator::LowerOrEqualThan => Some(left <= right),
+                    BinaryOperator::GreaterThan => Some(left > right),
+                    BinaryOperator::GreaterOrEqualThan => Some(left >= right),
+                    _ => None,
+                }
+            };
+            // comparisons with a constant are monotonic, checking both ends of the range is enough
+            let (at_min, at_max) = (compare(min)?, compare(max)?);
+            (at_min == at_max).then_some(at_min)
+        }
+    }
+}
+
+/// Replaces the comparisons of the `sdk_version_function` result with a constant that hold
+/// for every SDK version of the variant, either called directly or through a local
+struct SdkVersionCheckFolder<'a> {
+    function_name: &'a str,
+    variant: &'a SdkVariant,
+    sdk_version_locals: ScopedHashMap<String, bool>,
+}
+
+impl SdkVersionCheckFolder<'_> {
+    fn is_sdk_version(&self, expression: &Expression) -> bool {
+        match expression {
+            Expression::Call(call) => {
+                call.get_method().is_none()
+                    && match call.get_prefix() {
+                        nodes::Prefix::Identifier(identifier) => {
+                            identifier.get_name() == self.function_name
+                        }
+                        nodes::Prefix::Field(field) => {
+                            get_fqn(field).is_some_and(|name| name == self.function_name)
+                        }
+                        _ => false,
+                    }
+            }
+            Expression::Identifier(identifier) => self
+                .sdk_version_locals
+                .get(&identifier.get_name().to_string())
+                .copied()
+                .unwrap_or(false),
+            Expression::Parenthese(parenthese) => {
+                self.is_sdk_version(parenthese.inner_expression())
+            }
+            _ => false,
+        }
+    }
+}
+
+impl NodeProcessor for SdkVersionCheckFolder<'_> {
+    fn process_expression(&mut self, expression: &mut Expression) {
+        let Expression::Binary(binary) = expression else {
+            return;
+        };
+
+        let (sdk_version_on_left, value) = match (binary.left(), binary.right()) {
+            (left, Expression::Number(number)) if self.is_sdk_version(left) => {
+                (true, number.compute_value())
+            }
+            (Expression::Number(number), right) if self.is_sdk_version(right) => {
+                (false, number.compute_value())
+            }
+            _ => return,
+        };
+
+        match fold_comparison(binary.operator(), sdk_version_on_left, value, self.variant) {
+            Some(true) => *expression = Expression::True(None),
+            Some(false) => *expression = Expression::False(None),
+            None => {}
+        }
+    }
+}
+
+impl Scope for SdkVersionCheckFolder<'_> {
+    fn push(&mut self) {
+        self.sdk_version_locals.push();
+    }
+    fn pop(&mut self) {
+        self.sdk_version_locals.pop();
+    }
+    fn insert(&mut self, identifier: &mut String) {
+        self.sdk_version_locals.insert(identifier.clone(), false);
+    }
+    fn insert_local(&mut self, identifier: &mut String, value: Option<&mut nodes::Expression>) {
+        let is_sdk_version = value.is_some_and(|value| self.is_sdk_version(value));
+        self.sdk_version_locals
+            .insert(identifier.clone(), is_sdk_version);
+    }
+    fn insert_local_function(&mut self, function: &mut nodes::LocalFunctionStatement) {
+        self.sdk_version_locals
+            .insert(function.get_name().to_string(), false);
+    }
+    fn insert_self(&mut self) {}
+}
+
+/// darklua rule folding the SDK version checks of a bundle variant, so `compute_expression`
+/// and `remove_unused_if_branch` can strip the branches it will never run
+#[derive(Debug)]
+pub struct FoldSdkVersionChecks {
+    sdk_version_function: String,
+    variant: SdkVariant,
+}
+
+impl FoldSdkVersionChecks {
+    pub fn new(sdk_version_function: &str, variant: &SdkVariant) -> Self {
+        Self {
+            sdk_version_function: sdk_version_function.to_string(),
+            variant: variant.clone(),
+        }
+    }
+}
+
+impl FlawlessRule for FoldSdkVersionChecks {
+    fn flawless_process(&self, block: &mut nodes::Block, _: &Context) {
+        let mut folder = SdkVersionCheckFolder {
+            function_name: &self.sdk_version_function,
+            variant: &self.variant,
+            sdk_version_locals: ScopedHashMap::default(),
+        };
+        ScopeVisitor::visit_block(block, &mut folder);
+    }
+}
+
+impl RuleConfiguration for FoldSdkVersionChecks {
+    fn configure(&mut self, _: RuleProperties) -> Result<(), RuleConfigurationError> {
+        Ok(())
+    }
+
+    fn get_name(&self) -> &'static str {
+        "fold_sdk_version_checks"
+    }
+
+    fn serialize_to_properties(&self) -> RuleProperties {
+        RuleProperties::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fold_comparison() {
+        let modern = SdkVariant {
+            min: Some(26),
+            max: None,
+        };
+        let legacy = SdkVariant {
+            min: None,
+            max: Some(25),
+        };
+
+        // get_sdk_version() >= 26
+        let operator = BinaryOperator::GreaterOrEqualThan;
+        assert_eq!(fold_comparison(operator, true, 26.0, &modern), Some(true));
+        assert_eq!(fold_comparison(operator, true, 26.0, &legacy), Some(false));
+        assert_eq!(fold_comparison(operator, true, 30.0, &modern), None);
+
+        // 25 < get_sdk_version()
+        let operator = BinaryOperator::LowerThan;
+        assert_eq!(fold_comparison(operator, false, 25.0, &modern), Some(true));
+        assert_eq!(fold_comparison(operator, false, 25.0, &legacy), Some(false));
+
+        let operator = BinaryOperator::Equal;
+        assert_eq!(fold_comparison(operator, true, 20.0, &modern), Some(false));
+        assert_eq!(fold_comparison(operator, true, 20.0, &legacy), None);
+        let exact = SdkVariant {
+            min: Some(20),
+            max: Some(20),
+        };
+        assert_eq!(fold_comparison(operator, true, 20.0, &exact), Some(true));
+        assert_eq!(
+            fold_comparison(BinaryOperator::NotEqual, true, 20.0, &exact),
+            Some(false)
+        );
+    }
+}
@@ -9,7 +9,7 @@ use crate::commands::version::has_call_to_function_visitor::HasCallToFunctionVis
 use crate::commands::version::sdk_version::SdkVersionOut;
 use crate::commands::version::utils::get_fqn;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct VersionFile {
     /// What default MINIMUM version we should default to
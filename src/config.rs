@@ -1,21 +1,124 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
 use serde_derive::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
+    /// Files whose platforms are merged into `platforms` (`["platforms/*.toml"]`), relative
+    /// to this file
+    pub include: Option<Vec<String>>,
     pub settings: Settings,
+    /// Named sets of settings overrides, selected with `--profile`
+    pub profiles: Option<BTreeMap<String, Profile>>,
+    #[serde(default)]
     pub platforms: Vec<Platform>,
+    /// The profile applied to `settings`, if any
+    #[serde(skip)]
+    pub profile: Option<String>,
+    /// Variables of the `.env` file next to the config, to interpolate the selected profile
+    #[serde(skip)]
+    dotenv: HashMap<String, String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Settings {
     pub output_directory: String,
     pub definition_files: Option<Vec<String>>,
+    /// darklua generator, dense with a column span of 80 by default
+    pub generator: Option<GeneratorDefinition>,
+    /// darklua rules applied to every flow, after the injected globals
+    pub rules: Option<Vec<RuleDefinition>>,
+    /// Globals injected into every flow, platforms and flows override them
+    pub globals: Option<Globals>,
+    /// Feature flags of every flow, flows override them
+    pub features: Option<Features>,
+    /// Require aliases (`require("@shared/http")`), on top of the ones of `.luaurc` files
+    pub aliases: Option<BTreeMap<String, String>>,
+    /// Maximum size of a bundle, in bytes
+    pub max_bundle_size: Option<u64>,
+    /// Warn when a bundle grows by more than this percentage since the previous `bundle`
+    pub max_bundle_growth: Option<f64>,
+    /// Stamp every bundle with the CLI version, source commit and content hash
+    pub build_info: Option<BuildInfoSettings>,
+    /// Inject `RETRIEVES` and `MIN_SDK_VERSION` as strings, like older versions did
+    pub string_globals: Option<bool>,
+    /// Inject every flow and platform field as the `FLOW_METADATA` table
+    pub flow_metadata: Option<bool>,
+    /// Obfuscate every bundle, flows override it
+    pub obfuscation: Option<ObfuscationSettings>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BuildInfoSettings {
+    /// Prepend a comment with the build info to every bundle
+    #[serde(default = "default_true")]
+    pub banner: bool,
+    /// Inject the build info as the `BUILD_INFO` global table
+    #[serde(default = "default_true")]
+    pub global: bool,
+    /// Include the build time, off by default as it makes every build different
+    #[serde(default)]
+    pub timestamp: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ObfuscationSettings {
+    /// Turn it off on a flow to ship it readable when `[settings]` obfuscates every flow
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Give every local, parameter and local function a meaningless name
+    #[serde(default = "default_true")]
+    pub rename_locals: bool,
+    /// Replace string literals with a call decoding them at runtime
+    #[serde(default = "default_true")]
+    pub encode_strings: bool,
+    /// Give the bundled modules table and its keys meaningless names
+    #[serde(default = "default_true")]
+    pub module_names: bool,
+}
+
+/// A darklua rule, either by name (`"remove_types"`) or as a table with a `rule`
+/// field and its parameters (`{ rule = "remove_unused_variable" }`)
+pub type RuleDefinition = toml::Value;
+
+/// A darklua generator, either by name (`"readable"`) or as a table with a `name`
+/// field and its parameters (`{ name = "dense", column_span = 120 }`)
+pub type GeneratorDefinition = toml::Value;
+
+/// Extra globals injected into the bundle, by name: strings, numbers, booleans or tables
+pub type Globals = toml::Table;
+
+/// Compile-time feature flags, injected as boolean globals so disabled branches are removed
+pub type Features = BTreeMap<String, bool>;
+
+/// Overrides of `[settings]` for a build profile (`[profiles.<name>]`)
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Profile {
+    /// Defaults to `<settings.output_directory>/<profile>`, so profiles never share bundles or
+    /// `manifest.json`
+    pub output_directory: Option<String>,
+    pub generator: Option<GeneratorDefinition>,
+    /// Replaces the rules of `[settings]`
+    pub rules: Option<Vec<RuleDefinition>>,
+    /// Merged over the globals of `[settings]`
+    pub globals: Option<Globals>,
+    /// Merged over the features of `[settings]`
+    pub features: Option<Features>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Platform {
     pub name: String,
     pub description: String,
+    pub rules: Option<Vec<RuleDefinition>>,
+    pub max_bundle_size: Option<u64>,
+    pub max_bundle_growth: Option<f64>,
+    pub globals: Option<Globals>,
     pub flows: Vec<Flow>,
 }
 
@@ -23,6 +126,10 @@ pub struct Platform {
 pub struct SimplePlatform {
     pub name: String,
     pub description: String,
+    pub rules: Option<Vec<RuleDefinition>>,
+    pub max_bundle_size: Option<u64>,
+    pub max_bundle_growth: Option<f64>,
+    pub globals: Option<Globals>,
 }
 
 impl From<Platform> for SimplePlatform {
@@ -30,6 +137,10 @@ impl From<Platform> for SimplePlatform {
         Self {
             name: platform.name,
             description: platform.description,
+            rules: platform.rules,
+            max_bundle_size: platform.max_bundle_size,
+            max_bundle_growth: platform.max_bundle_growth,
+            globals: platform.globals,
         }
     }
 }
@@ -39,6 +150,10 @@ impl From<&Platform> for SimplePlatform {
         Self {
             name: platform.name.clone(),
             description: platform.description.clone(),
+            rules: platform.rules.clone(),
+            max_bundle_size: platform.max_bundle_size,
+            max_bundle_growth: platform.max_bundle_growth,
+            globals: platform.globals.clone(),
         }
     }
 }
@@ -52,15 +167,384 @@ pub struct Flow {
     pub min_sdk_version: Option<String>,
     pub retrieves: Option<Vec<String>>,
     pub path: String,
+    pub rules: Option<Vec<RuleDefinition>>,
+    pub max_bundle_size: Option<u64>,
+    pub max_bundle_growth: Option<f64>,
+    pub globals: Option<Globals>,
+    pub features: Option<Features>,
+    /// Extra bundles for SDK ranges, with the SDK version checks folded at build time
+    pub variants: Option<Vec<SdkVariant>>,
+    /// Replaces the obfuscation settings of `[settings]`
+    pub obfuscation: Option<ObfuscationSettings>,
+}
+
+/// SDK range of a bundle variant, both ends inclusive
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct SdkVariant {
+    pub min: Option<u64>,
+    pub max: Option<u64>,
+}
+
+impl SdkVariant {
+    /// `26-`, `0-25` or `20-25`, used in the bundle file name
+    pub fn range(&self) -> String {
+        format!(
+            "{}-{}",
+            self.min.unwrap_or(0),
+            self.max.map(|max| max.to_string()).unwrap_or_default()
+        )
+    }
+}
+
+/// A file listed in `include`, it can only declare platforms
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IncludedConfig {
+    #[serde(default)]
+    platforms: Vec<Platform>,
+}
+
+/// Whether a file name matches a pattern with `*` and `?` wildcards
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (
+        pattern.chars().collect::<Vec<_>>(),
+        name.chars().collect::<Vec<_>>(),
+    );
+    let (mut p, mut n) = (0, 0);
+    // where the last `*` was, and where in `name` it matches up to
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(char) if *char == '?' || *char == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|char| *char == '*')
+}
+
+/// Files matching an `include` pattern, wildcards are allowed in any path component
+fn expand_include(base: &Path, pattern: &str) -> Vec<PathBuf> {
+    let mut paths = vec![base.to_path_buf()];
+
+    for component in pattern.split(['/', '\\']) {
+        if component.is_empty() || component == "." {
+            continue;
+        }
+        if !component.contains(['*', '?']) {
+            paths = paths.iter().map(|path| path.join(component)).collect();
+            continue;
+        }
+
+        paths = paths
+            .iter()
+            .filter_map(|path| {
+                let directory = if path.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    path
+                };
+                std::fs::read_dir(directory).ok()
+            })
+            .flat_map(|entries| entries.flatten())
+            .filter(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                // like shells, `*` doesn't match hidden files
+                (!name.starts_with('.') || component.starts_with('.'))
+                    && wildcard_match(component, &name)
+            })
+            .map(|entry| entry.path())
+            .collect();
+    }
+
+    let mut files = paths
+        .into_iter()
+        .filter(|path| path.is_file())
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+/// Platforms sharing a name and flows sharing an alias, along with the files declaring them
+fn find_duplicates(platforms: &[Platform], files: &[String]) -> Vec<String> {
+    let mut platform_files: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    let mut flow_files: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (platform, file) in platforms.iter().zip(files) {
+        platform_files.entry(&platform.name).or_default().push(file);
+        for flow in &platform.flows {
+            flow_files.entry(&flow.alias).or_default().push(file);
+        }
+    }
+
+    let duplicates = |kind: &str, declarations: BTreeMap<&str, Vec<&str>>| {
+        declarations
+            .into_iter()
+            .filter(|(_, files)| files.len() > 1)
+            .map(|(name, files)| format!("{} `{}` is declared in {}", kind, name, files.join(", ")))
+            .collect::<Vec<_>>()
+    };
+    let mut found = duplicates("platform", platform_files);
+    found.extend(duplicates("flow alias", flow_files));
+    found
+}
+
+/// Reads a config file and interpolates its strings, but those of `[profiles]`: only the
+/// selected profile is, by `apply_profile`, so the others can use variables that aren't set
+fn read_config_value(
+    path: &Path,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> anyhow::Result<toml::Value> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let mut table = content
+        .parse::<toml::Table>()
+        .map_err(|e| anyhow::anyhow!("Failed to parse {}: {}", path.display(), e))?;
+
+    let profiles = table.remove("profiles");
+    let mut value = toml::Value::Table(table);
+    interpolate_value(&mut value, "", lookup)
+        .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+    if let (toml::Value::Table(table), Some(profiles)) = (&mut value, profiles) {
+        table.insert("profiles".to_string(), profiles);
+    }
+    Ok(value)
+}
+
+/// Value of a variable: from the environment, or else from the `.env` file
+fn lookup_variable(dotenv: &HashMap<String, String>, name: &str) -> Option<String> {
+    std::env::var(name)
+        .ok()
+        .or_else(|| dotenv.get(name).cloned())
+}
+
+/// Variables of the optional `.env` file next to the config: `NAME=value` lines, `#` comments
+fn parse_dotenv(content: &str) -> anyhow::Result<HashMap<String, String>> {
+    let mut variables = HashMap::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("line {}: expected NAME=value", index + 1))?;
+        let value = value.trim();
+        let value = [('"', '"'), ('\'', '\'')]
+            .iter()
+            .find_map(|(open, close)| value.strip_prefix(*open)?.strip_suffix(*close))
+            .unwrap_or(value);
+        variables.insert(name.trim().to_string(), value.to_string());
+    }
+    Ok(variables)
+}
+
+/// Replaces `${NAME}` and `${NAME:-default}` with the value of the variable, the default being
+/// used when it is unset or empty. `$$` is a literal `$`
+fn interpolate(value: &str, lookup: &dyn Fn(&str) -> Option<String>) -> anyhow::Result<String> {
+    let mut interpolated = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(index) = rest.find('$') {
+        interpolated.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            interpolated.push('$');
+            rest = after;
+            continue;
+        }
+        let Some(after) = rest.strip_prefix('{') else {
+            interpolated.push('$');
+            continue;
+        };
+        let end = after
+            .find('}')
+            .ok_or_else(|| anyhow::anyhow!("unterminated `${{` in \"{}\"", value))?;
+
+        let (name, default) = match after[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&after[..end], None),
+        };
+        let is_valid_name = name
+            .chars()
+            .next()
+            .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+            && name
+                .chars()
+                .all(|char| char.is_ascii_alphanumeric() || char == '_');
+        if !is_valid_name {
+            anyhow::bail!("invalid variable name `{}` in \"{}\"", name, value);
+        }
+
+        let variable = lookup(name).filter(|variable| !variable.is_empty());
+        match (variable, default) {
+            (Some(variable), _) => interpolated.push_str(&variable),
+            (None, Some(default)) => interpolated.push_str(default),
+            (None, None) => anyhow::bail!("environment variable {} is not set", name),
+        }
+        rest = &after[end + 1..];
+    }
+
+    interpolated.push_str(rest);
+    Ok(interpolated)
+}
+
+/// Interpolates every string of the config, `key` is where `value` is in it
+fn interpolate_value(
+    value: &mut toml::Value,
+    key: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> anyhow::Result<()> {
+    match value {
+        toml::Value::String(string) => {
+            *string =
+                interpolate(string, lookup).map_err(|e| anyhow::anyhow!("{} (in {})", e, key))?;
+        }
+        toml::Value::Array(array) => {
+            for (index, value) in array.iter_mut().enumerate() {
+                interpolate_value(value, &format!("{}[{}]", key, index), lookup)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (name, value) in table.iter_mut() {
+                let key = if key.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}.{}", key, name)
+                };
+                interpolate_value(value, &key, lookup)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
 }
 
 impl Config {
+    /// Reads the config and the files it includes, interpolating `${NAME}` from the
+    /// environment and the `.env` file next to it, the environment taking precedence
     pub fn from_file(path: &str) -> anyhow::Result<Self> {
-        let content = std::fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&content)?;
+        let dotenv_path = Path::new(path).with_file_name(".env");
+        let dotenv = match std::fs::read_to_string(&dotenv_path) {
+            Ok(content) => parse_dotenv(&content)
+                .map_err(|e| anyhow::anyhow!("Invalid {}: {}", dotenv_path.display(), e))?,
+            Err(_) => HashMap::new(),
+        };
+        let lookup = |name: &str| lookup_variable(&dotenv, name);
+
+        let mut config: Config = read_config_value(Path::new(path), &lookup)?.try_into()?;
+
+        let base = Path::new(path).parent().unwrap_or(Path::new(""));
+        let mut platform_files = vec![path.to_string(); config.platforms.len()];
+        let mut included = BTreeSet::from([std::fs::canonicalize(path)?]);
+        for pattern in config.include.iter().flatten() {
+            let files = expand_include(base, pattern);
+            if files.is_empty() {
+                anyhow::bail!("{}: include \"{}\" matches no file", path, pattern);
+            }
+
+            for file in files {
+                // a file matched by several patterns is only merged once
+                if !included.insert(std::fs::canonicalize(&file)?) {
+                    continue;
+                }
+                let included_config: IncludedConfig = read_config_value(&file, &lookup)?
+                    .try_into()
+                    .map_err(|e| anyhow::anyhow!("Invalid {}: {}", file.display(), e))?;
+                platform_files.resize(
+                    platform_files.len() + included_config.platforms.len(),
+                    file.display().to_string(),
+                );
+                config.platforms.extend(included_config.platforms);
+            }
+        }
+
+        let duplicates = find_duplicates(&config.platforms, &platform_files);
+        if !duplicates.is_empty() {
+            anyhow::bail!("{}:\n  {}", path, duplicates.join("\n  "));
+        }
+
+        config.dotenv = dotenv;
+        Ok(config)
+    }
+
+    pub fn from_file_with_profile(path: &str, profile: Option<&str>) -> anyhow::Result<Self> {
+        let mut config = Self::from_file(path)?;
+        if let Some(profile) = profile {
+            config.apply_profile(profile)?;
+        }
         Ok(config)
     }
 
+    /// Overrides `settings` with the given profile, once interpolated
+    pub fn apply_profile(&mut self, name: &str) -> anyhow::Result<()> {
+        let profiles = self.profiles.as_ref();
+        let profile = profiles
+            .and_then(|profiles| profiles.get(name))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Unknown profile {}, available profiles: {}",
+                    name,
+                    profiles
+                        .map(|profiles| profiles.keys().cloned().collect::<Vec<_>>().join(", "))
+                        .filter(|names| !names.is_empty())
+                        .unwrap_or_else(|| "none".to_string())
+                )
+            })?;
+
+        let mut value = toml::Value::try_from(profile)?;
+        interpolate_value(&mut value, &format!("profiles.{}", name), &|variable| {
+            lookup_variable(&self.dotenv, variable)
+        })?;
+        let profile: Profile = value.try_into()?;
+
+        self.settings.output_directory = match profile.output_directory {
+            Some(output_directory) => output_directory,
+            None => format!(
+                "{}/{}",
+                self.settings.output_directory.trim_end_matches(['/', '\\']),
+                name
+            ),
+        };
+        if let Some(generator) = profile.generator {
+            self.settings.generator = Some(generator);
+        }
+        if let Some(rules) = profile.rules {
+            self.settings.rules = Some(rules);
+        }
+        if let Some(globals) = profile.globals {
+            self.settings
+                .globals
+                .get_or_insert_with(Globals::new)
+                .extend(globals);
+        }
+        if let Some(features) = profile.features {
+            self.settings
+                .features
+                .get_or_insert_with(Features::new)
+                .extend(features);
+        }
+        self.profile = Some(name.to_string());
+
+        Ok(())
+    }
+
     pub fn get_flows_paths(&self) -> Vec<String> {
         let mut files = Vec::new();
         let current_dir_path = std::env::current_dir().unwrap();
@@ -92,3 +576,280 @@ impl Config {
         None
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_interpolate() {
+        let lookup = |name: &str| match name {
+            "API_URL" => Some("https://api.example.com".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+
+        assert_eq!(
+            interpolate("${API_URL}/v1", &lookup).unwrap(),
+            "https://api.example.com/v1"
+        );
+        assert_eq!(interpolate("${OUT:-out}/dev", &lookup).unwrap(), "out/dev");
+        assert_eq!(
+            interpolate("${EMPTY:-default}", &lookup).unwrap(),
+            "default"
+        );
+        assert_eq!(
+            interpolate("$$5 or $5, $${API_URL}", &lookup).unwrap(),
+            "$5 or $5, ${API_URL}"
+        );
+
+        let error = interpolate("${TOKEN}", &lookup).unwrap_err().to_string();
+        assert!(error.contains("TOKEN"));
+        assert!(interpolate("${API_URL", &lookup).is_err());
+        assert!(interpolate("${1ABC}", &lookup).is_err());
+
+        let mut value: toml::Value =
+            toml::from_str("[settings]\nglobals = { TOKEN = \"${TOKEN}\" }").unwrap();
+        let error = interpolate_value(&mut value, "", &lookup)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("settings.globals.TOKEN"));
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*.toml", "android.toml"));
+        assert!(wildcard_match("flows-?.toml", "flows-a.toml"));
+        assert!(wildcard_match("*", "ios.toml"));
+        assert!(wildcard_match("a*b*c", "aXXbYYbc"));
+        assert!(!wildcard_match("*.toml", "android.toml.bak"));
+        assert!(!wildcard_match("flows-?.toml", "flows-ab.toml"));
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let platform = |name: &str, aliases: &[&str]| -> Platform {
+            let flows = aliases
+                .iter()
+                .map(|alias| {
+                    format!(
+                        "{{ name = \"{0}\", alias = \"{0}\", description = \"\", path = \"flow.luau\" }}",
+                        alias
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            toml::from_str(&format!(
+                "name = \"{}\"\ndescription = \"\"\nflows = [{}]",
+                name, flows
+            ))
+            .unwrap()
+        };
+
+        let platforms = vec![
+            platform("android", &["login"]),
+            platform("ios", &["login", "checkout"]),
+            platform("android", &["signup"]),
+        ];
+        let files = [
+            "opacity.toml".to_string(),
+            "platforms/ios.toml".to_string(),
+            "platforms/android.toml".to_string(),
+        ];
+
+        assert_eq!(
+            find_duplicates(&platforms, &files),
+            vec![
+                "platform `android` is declared in opacity.toml, platforms/android.toml",
+                "flow alias `login` is declared in opacity.toml, platforms/ios.toml",
+            ]
+        );
+        assert_eq!(find_duplicates(&platforms[..2], &files[..2]).len(), 1);
+    }
+
+    #[test]
+    fn test_parse_dotenv() {
+        let variables = parse_dotenv(
+            "# local overrides\nAPI_URL=http://localhost:8080\nexport TOKEN=\"a b\"\n\n",
+        )
+        .unwrap();
+        assert_eq!(variables["API_URL"], "http://localhost:8080");
+        assert_eq!(variables["TOKEN"], "a b");
+        assert!(parse_dotenv("NOT A VARIABLE").is_err());
+    }
+
+    #[test]
+    fn test_apply_profile_interpolates_only_the_selected_profile() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [settings]
+            output_directory = "out"
+
+            [profiles.dev]
+            output_directory = "${OPACITY_CLI_TEST_DEV_OUT:-out/dev}"
+
+            [profiles.prod]
+            globals = { TOKEN = "${OPACITY_CLI_TEST_UNSET_TOKEN}" }
+            "#,
+        )
+        .unwrap();
+        config.dotenv = HashMap::from([(
+            "OPACITY_CLI_TEST_DEV_OUT".to_string(),
+            "build/dev".to_string(),
+        )]);
+
+        let error = config.apply_profile("prod").unwrap_err().to_string();
+        assert!(error.contains("profiles.prod.globals.TOKEN"));
+
+        config.apply_profile("dev").unwrap();
+        assert_eq!(config.settings.output_directory, "build/dev");
+    }
+
+    #[test]
+    fn test_apply_profile() {
+        let content = r#"
+            [settings]
+            output_directory = "out"
+            rules = ["remove_types"]
+            globals = { BASE_URL = "https://api.example.com", DEBUG = true }
+            features = { VERBOSE_LOGS = true, NEW_LOGIN = true }
+
+            [profiles.prod]
+            output_directory = "out/prod"
+            rules = ["remove_types", "remove_debug_profiling"]
+            globals = { DEBUG = false }
+            features = { VERBOSE_LOGS = false }
+
+            [profiles.staging]
+            globals = { BASE_URL = "https://staging.example.com" }
+
+            [[platforms]]
+            name = "example"
+            description = "Example platform"
+            flows = []
+            "#;
+        let mut config: Config = toml::from_str(content).unwrap();
+
+        assert!(config.apply_profile("dev").is_err());
+
+        let mut staging: Config = toml::from_str(content).unwrap();
+        staging.apply_profile("staging").unwrap();
+        assert_eq!(staging.settings.output_directory, "out/staging");
+
+        config.apply_profile("prod").unwrap();
+        assert_eq!(config.profile.as_deref(), Some("prod"));
+        assert_eq!(config.settings.output_directory, "out/prod");
+        assert_eq!(config.settings.rules.as_ref().unwrap().len(), 2);
+        let globals = config.settings.globals.unwrap();
+        assert_eq!(globals["DEBUG"], toml::Value::Boolean(false));
+        assert_eq!(
+            globals["BASE_URL"],
+            toml::Value::String("https://api.example.com".to_string())
+        );
+        let features = config.settings.features.unwrap();
+        assert!(!features["VERBOSE_LOGS"]);
+        assert!(features["NEW_LOGIN"]);
+    }
+
+    #[test]
+    fn test_rules_merge_order() {
+        use crate::commands::bundle::get_configured_rules;
+        use darklua_core::rules::RuleConfiguration;
+
+        let content = r#"
+            [settings]
+            output_directory = "out"
+            rules = ["remove_types"]
+
+            [profiles.prod]
+            rules = ["remove_assertions"]
+
+            [[platforms]]
+            name = "example"
+            description = "Example platform"
+            rules = ["remove_comments"]
+            flows = [
+                { name = "login", alias = "login", description = "", path = "login.luau", rules = ["remove_spaces"] },
+            ]
+            "#;
+        let rule_names = |config: &Config| {
+            let platform = &config.platforms[0];
+            get_configured_rules(config, &SimplePlatform::from(platform), &platform.flows[0])
+                .unwrap()
+                .iter()
+                .map(|rule| rule.get_name())
+                .collect::<Vec<_>>()
+        };
+
+        let mut config: Config = toml::from_str(content).unwrap();
+        assert_eq!(
+            rule_names(&config),
+            vec!["remove_types", "remove_comments", "remove_spaces"]
+        );
+
+        config.apply_profile("prod").unwrap();
+        assert_eq!(
+            rule_names(&config),
+            vec!["remove_assertions", "remove_comments", "remove_spaces"]
+        );
+    }
+
+    #[test]
+    fn test_globals_and_features_merge_order() {
+        use crate::commands::bundle::{
+            get_custom_global_rules, get_feature_rules, get_features, get_globals,
+        };
+
+        let content = r#"
+            [settings]
+            output_directory = "out"
+            globals = { API_URL = "settings", TIMEOUT = 10, DEBUG = true }
+            features = { NEW_LOGIN = false, VERBOSE_LOGS = true }
+
+            [profiles.prod]
+            globals = { DEBUG = false }
+            features = { VERBOSE_LOGS = false }
+
+            [[platforms]]
+            name = "example"
+            description = "Example platform"
+            globals = { API_URL = "platform", TIMEOUT = 20 }
+            flows = [
+                { name = "login", alias = "login", description = "", path = "login.luau", globals = { API_URL = "flow" }, features = { NEW_LOGIN = true } },
+                { name = "signup", alias = "signup", description = "", path = "signup.luau", globals = { FLOW_NAME = "signup" } },
+                { name = "checkout", alias = "checkout", description = "", path = "checkout.luau", features = { TIMEOUT = true } },
+            ]
+            "#;
+        let mut config: Config = toml::from_str(content).unwrap();
+        config.apply_profile("prod").unwrap();
+        let platform = SimplePlatform::from(&config.platforms[0]);
+        let [login, signup, checkout] = &config.platforms[0].flows[..] else {
+            panic!("expected three flows");
+        };
+
+        let globals = get_globals(&config.settings, &platform, login);
+        assert_eq!(globals["API_URL"], toml::Value::String("flow".to_string()));
+        assert_eq!(globals["TIMEOUT"], toml::Value::Integer(20));
+        assert_eq!(globals["DEBUG"], toml::Value::Boolean(false));
+        assert_eq!(
+            get_features(&config.settings, login),
+            Features::from([
+                ("NEW_LOGIN".to_string(), true),
+                ("VERBOSE_LOGS".to_string(), false),
+            ])
+        );
+
+        assert_eq!(
+            get_custom_global_rules(&config.settings, &platform, signup)
+                .unwrap_err()
+                .to_string(),
+            "signup: `FLOW_NAME` is injected by opacity-cli and can't be redefined in `globals`"
+        );
+        assert_eq!(
+            get_feature_rules(&config.settings, &platform, checkout)
+                .unwrap_err()
+                .to_string(),
+            "checkout: feature `TIMEOUT` has the same name as an injected global"
+        );
+    }
+}
@@ -0,0 +1,259 @@
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use darklua_core::DarkluaError;
+use serde::Serialize;
+
+/// How many lines are shown on each side of the reported line in a code frame
+const CODE_FRAME_CONTEXT: usize = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum DiagnosticFormat {
+    /// Readable messages with a code frame
+    #[default]
+    Human,
+    /// One JSON object per line, for editors and CI
+    Json,
+}
+
+static FORMAT: OnceLock<DiagnosticFormat> = OnceLock::new();
+
+pub fn set_format(format: DiagnosticFormat) {
+    FORMAT.get_or_init(|| format);
+}
+
+/// A problem in a source file, printed to stderr
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Kind of problem: `parse`, `require`, `rule`, `resource`, `validation`, `bundle-size` or
+    /// `other`
+    pub code: &'static str,
+    pub message: String,
+    pub file: Option<PathBuf>,
+    /// 1-based
+    pub line: Option<usize>,
+    /// 1-based
+    pub column: Option<usize>,
+}
+
+fn parse_number(text: &str) -> Option<usize> {
+    let digits = text
+        .chars()
+        .take_while(|char| char.is_ascii_digit())
+        .collect::<String>();
+    digits.parse().ok()
+}
+
+/// Finds a location in an error message, either as `line 3, character 5`, `line 3, column 5`
+/// or `:3:5`
+fn find_location(message: &str) -> Option<(usize, Option<usize>)> {
+    if let Some(index) = message.find("line ") {
+        let rest = &message[index + "line ".len()..];
+        if let Some(line) = parse_number(rest) {
+            let column = [", character ", ", column "]
+                .iter()
+                .find_map(|separator| {
+                    rest.find(separator)
+                        .filter(|position| *position <= line.to_string().len())
+                        .map(|position| &rest[position + separator.len()..])
+                })
+                .and_then(parse_number);
+            return Some((line, column));
+        }
+    }
+
+    message.match_indices(':').find_map(|(index, _)| {
+        let rest = &message[index + 1..];
+        let line = parse_number(rest)?;
+        let column = rest[line.to_string().len()..]
+            .strip_prefix(':')
+            .and_then(parse_number)?;
+        Some((line, Some(column)))
+    })
+}
+
+/// The first path quoted with backticks in an error message that exists on disk
+fn find_file(message: &str) -> Option<PathBuf> {
+    message
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .map(PathBuf::from)
+        .find(|path| path.is_file())
+}
+
+fn paint(text: &str, color: &str) -> String {
+    if std::io::stderr().is_terminal() {
+        format!("\x1b[{}m{}\x1b[0m", color, text)
+    } else {
+        text.to_string()
+    }
+}
+
+impl Diagnostic {
+    fn new(severity: Severity, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            code,
+            message: message.into(),
+            file: None,
+            line: None,
+            column: None,
+        }
+    }
+
+    pub fn error(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, code, message)
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, code, message)
+    }
+
+    pub fn with_file(mut self, file: &Path) -> Self {
+        self.file = Some(file.to_path_buf());
+        self
+    }
+
+    pub fn with_location(mut self, line: usize, column: Option<usize>) -> Self {
+        self.line = Some(line);
+        self.column = column;
+        self
+    }
+
+    /// For darklua and parser errors, which only expose a message: reads the location from it
+    pub fn with_location_from_message(self) -> Self {
+        match find_location(&self.message) {
+            Some((line, column)) => self.with_location(line, column),
+            None => self,
+        }
+    }
+
+    /// darklua errors only expose their message, the kind, file and location are read from it
+    pub fn from_darklua_error(error: &DarkluaError) -> Self {
+        let message = error.to_string();
+        let code = if message.contains("unable to parse") {
+            "parse"
+        } else if message.contains("require") {
+            "require"
+        } else if message.contains("error processing") {
+            "rule"
+        } else if message.contains("unable to find") || message.contains("unable to read") {
+            "resource"
+        } else {
+            "other"
+        };
+
+        let file = find_file(&message);
+        let diagnostic = Self::error(code, message).with_location_from_message();
+        match file {
+            Some(file) => diagnostic.with_file(&file),
+            None => diagnostic,
+        }
+    }
+
+    /// The reported line and the lines around it, with a caret under the column
+    fn code_frame(&self) -> Option<String> {
+        let line = self.line?;
+        let content = std::fs::read_to_string(self.file.as_ref()?).ok()?;
+        let lines = content.lines().collect::<Vec<_>>();
+        if line == 0 || line > lines.len() {
+            return None;
+        }
+
+        let first = line.saturating_sub(CODE_FRAME_CONTEXT).max(1);
+        let last = (line + CODE_FRAME_CONTEXT).min(lines.len());
+        let gutter_width = last.to_string().len();
+        let gutter = |text: &str| paint(&format!("{:>gutter_width$} |", text), "34");
+
+        let mut frame = vec![gutter("")];
+        for number in first..=last {
+            frame.push(format!(
+                "{} {}",
+                gutter(&number.to_string()),
+                lines[number - 1]
+            ));
+            if number == line {
+                if let Some(column) = self.column {
+                    frame.push(format!(
+                        "{} {}{}",
+                        gutter(""),
+                        " ".repeat(column.saturating_sub(1)),
+                        paint("^", "1;31")
+                    ));
+                }
+            }
+        }
+        Some(frame.join("\n"))
+    }
+
+    fn render(&self) -> String {
+        let severity = match self.severity {
+            Severity::Error => paint("error", "1;31"),
+            Severity::Warning => paint("warning", "1;33"),
+        };
+        let mut rendered = format!("{}[{}]: {}", severity, self.code, self.message);
+
+        if let Some(file) = &self.file {
+            let mut location = file.display().to_string().replace('\\', "/");
+            if let Some(line) = self.line {
+                location.push_str(&format!(":{}", line));
+                if let Some(column) = self.column {
+                    location.push_str(&format!(":{}", column));
+                }
+            }
+            rendered.push_str(&format!("\n  {} {}", paint("-->", "34"), location));
+        }
+        if let Some(code_frame) = self.code_frame() {
+            rendered.push('\n');
+            rendered.push_str(&code_frame);
+        }
+        rendered
+    }
+
+    /// Prints the diagnostic to stderr, in the format selected with `--diagnostics`
+    pub fn emit(&self) {
+        match FORMAT.get().copied().unwrap_or_default() {
+            DiagnosticFormat::Human => eprintln!("{}", self.render()),
+            DiagnosticFormat::Json => match serde_json::to_string(self) {
+                Ok(json) => eprintln!("{}", json),
+                Err(_) => eprintln!("{}", self.render()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_location() {
+        assert_eq!(
+            find_location(
+                "unexpected token `end`. (starting from line 3, character 5 and ending on line 3, character 8)"
+            ),
+            Some((3, Some(5)))
+        );
+        assert_eq!(
+            find_location("unexpected symbol at line 12, column 1"),
+            Some((12, Some(1)))
+        );
+        assert_eq!(
+            find_location("error in src/flow.luau:7:14"),
+            Some((7, Some(14)))
+        );
+        assert_eq!(find_location("expected `)` on line 4"), Some((4, None)));
+        assert_eq!(find_location("unable to find `./missing`"), None);
+    }
+}
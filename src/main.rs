@@ -1,22 +1,40 @@
+pub mod aliases;
 pub mod config;
+pub mod diagnostics;
+pub mod paths;
 mod commands {
     pub mod analyze;
     pub mod bundle;
+    pub mod clean;
+    pub mod diff;
     pub mod generate_completions;
+    pub mod keys;
+    pub mod package;
     pub mod serve;
+    pub mod shared_modules;
+    pub mod symbolicate;
+    pub mod verify;
     pub mod version;
 }
 
 use commands::analyze::analyze;
-use commands::bundle::bundle;
+use commands::bundle::{bundle, BundleArgs};
+use commands::clean::clean;
+use commands::diff::{diff, DiffArgs};
 use commands::generate_completions::generate_completions;
+use commands::keys::{keys, KeysCommands};
+use commands::package::{inspect, package, PackageArgs};
 use commands::serve::serve;
+use commands::shared_modules::shared_modules;
+use commands::symbolicate::symbolicate;
+use commands::verify::verify;
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use tracing::Level;
 
 use crate::commands::version::compute_versions;
+use crate::diagnostics::DiagnosticFormat;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -24,6 +42,15 @@ struct Cli {
     #[arg(short, long, default_value = "opacity.toml")]
     config: String,
 
+    /// Build profile from opacity.toml to apply on top of `[settings]` (not accepted by
+    /// `completions`, `compute-versions`, `keys` and `inspect`, which don't use `[settings]`)
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
+    /// How parse, require and rule errors are printed
+    #[arg(long, value_enum, global = true, default_value = "human")]
+    diagnostics: DiagnosticFormat,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -31,11 +58,14 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Bundle all Luau files
-    Bundle,
+    Bundle(BundleArgs),
 
     /// Analyze all Luau files
     Analyze,
 
+    /// Remove the bundles, source maps, signatures, manifest and lock files
+    Clean,
+
     /// Generate completions for a given shell
     #[command(name = "completions")]
     GenerateCompletions {
@@ -53,16 +83,104 @@ enum Commands {
     /// Compute versions for all flows
     #[command(name = "compute-versions")]
     ComputeVersions,
+
+    /// Report the modules embedded by several flows, their total size and whether their copies differ
+    #[command(name = "shared-modules")]
+    SharedModules {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Rewrite a stack trace or error message from bundle locations to source locations
+    Symbolicate {
+        /// Flow to use for chunk names that don't match a flow alias
+        #[arg(short, long)]
+        flow: Option<String>,
+
+        /// File containing the stack trace (reads from stdin if omitted)
+        input: Option<String>,
+    },
+
+    /// Manage the keys used to sign bundles
+    Keys {
+        #[command(subcommand)]
+        command: KeysCommands,
+    },
+
+    /// Verify every bundle against its signature and hashes.lock
+    Verify {
+        /// Ed25519 public key the bundles were signed for
+        #[arg(short, long, value_name = "KEY")]
+        public_key: String,
+    },
+
+    /// Bundle every flow and write a release archive with the bundles, manifest and lock files
+    Package(PackageArgs),
+
+    /// Show what changed in a flow's bundle, module by module, against another build
+    Diff(DiffArgs),
+
+    /// List the files of a release archive and check its bundles against their hashes
+    Inspect {
+        /// Archive written by `package`
+        archive: String,
+
+        /// Also verify the signatures of the bundles with this Ed25519 public key
+        #[arg(short, long, value_name = "KEY")]
+        public_key: Option<String>,
+    },
+}
+
+impl Commands {
+    /// Name of the command when it doesn't use the `[settings]` a profile overrides
+    fn ignores_profile(&self) -> Option<&'static str> {
+        match self {
+            Commands::GenerateCompletions { .. } => Some("completions"),
+            Commands::ComputeVersions => Some("compute-versions"),
+            Commands::Keys { .. } => Some("keys"),
+            Commands::Inspect { .. } => Some("inspect"),
+            _ => None,
+        }
+    }
 }
 
 async fn run() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
+    diagnostics::set_format(cli.diagnostics);
+    if let (Some(profile), Some(command)) = (&cli.profile, cli.command.ignores_profile()) {
+        return Err(format!(
+            "`{}` doesn't use build profiles, remove `--profile {}`",
+            command, profile
+        )
+        .into());
+    }
     match &cli.command {
-        Commands::Bundle => bundle(&cli.config, false)?,
-        Commands::Analyze => analyze(&cli.config)?,
+        Commands::Bundle(args) => bundle(&cli.config, cli.profile.as_deref(), false, args)?,
+        Commands::Analyze => analyze(&cli.config, cli.profile.as_deref())?,
+        Commands::Clean => clean(&cli.config, cli.profile.as_deref())?,
         Commands::GenerateCompletions { shell } => generate_completions(shell)?,
-        Commands::Serve { rebundle } => serve(&cli.config, *rebundle).await?,
+        Commands::Serve { rebundle } => {
+            serve(&cli.config, cli.profile.as_deref(), *rebundle).await?
+        }
         Commands::ComputeVersions => compute_versions(&cli.config)?,
+        Commands::SharedModules { json } => {
+            shared_modules(&cli.config, cli.profile.as_deref(), *json)?
+        }
+        Commands::Symbolicate { flow, input } => symbolicate(
+            &cli.config,
+            cli.profile.as_deref(),
+            flow.as_deref(),
+            input.as_deref(),
+        )?,
+        Commands::Keys { command } => keys(command)?,
+        Commands::Verify { public_key } => verify(&cli.config, cli.profile.as_deref(), public_key)?,
+        Commands::Diff(args) => diff(&cli.config, cli.profile.as_deref(), args)?,
+        Commands::Package(args) => package(&cli.config, cli.profile.as_deref(), args)?,
+        Commands::Inspect {
+            archive,
+            public_key,
+        } => inspect(archive, public_key.as_deref())?,
     }
     Ok(())
 }
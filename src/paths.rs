@@ -0,0 +1,94 @@
+use std::path::{Component, Path, PathBuf};
+
+/// Makes `path` relative to `base`, both being relative to the current directory or absolute
+pub fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    fn components(path: &Path) -> Vec<Component<'_>> {
+        let mut components = Vec::new();
+        for component in path.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    components.pop();
+                }
+                _ => components.push(component),
+            }
+        }
+        components
+    }
+    let current_dir = std::env::current_dir().unwrap_or_default();
+    let (path, base) = (current_dir.join(path), current_dir.join(base));
+    let (path_components, base_components) = (components(&path), components(&base));
+
+    let common = path_components
+        .iter()
+        .zip(base_components.iter())
+        .take_while(|(lhs, rhs)| lhs == rhs)
+        .count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..base_components.len() {
+        relative.push("..");
+    }
+    for component in &path_components[common..] {
+        relative.push(component.as_os_str());
+    }
+    relative
+}
+
+/// A fresh directory in the system's temporary directory, removed with its content once
+/// dropped
+#[cfg(test)]
+pub struct TestDirectory(PathBuf);
+
+#[cfg(test)]
+impl TestDirectory {
+    pub fn create() -> Self {
+        let path = std::env::temp_dir().join(format!("opacity-cli-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestDirectory {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDirectory {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_relative_to() {
+        assert_eq!(
+            relative_to(Path::new("out/a.bundle.luau"), Path::new("")),
+            PathBuf::from("out/a.bundle.luau")
+        );
+        assert_eq!(
+            relative_to(Path::new("./out/a.bundle.luau"), Path::new("project")),
+            PathBuf::from("../out/a.bundle.luau")
+        );
+        assert_eq!(
+            relative_to(
+                Path::new("/home/alice/proj/out/a.bundle.luau"),
+                Path::new("/home/alice/proj")
+            ),
+            PathBuf::from("out/a.bundle.luau")
+        );
+        assert_eq!(
+            relative_to(Path::new("project/../shared/x.luau"), Path::new("project")),
+            PathBuf::from("../shared/x.luau")
+        );
+    }
+}